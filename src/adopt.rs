@@ -0,0 +1,153 @@
+use log::debug;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::errors::TowError;
+use crate::store::BinaryEntry;
+
+/// Some binaries wait for input or start a server instead of printing their version.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unmanaged file in binaries_dir, with a registry that knows its name if one does.
+#[derive(Serialize)]
+pub struct AdoptCandidate {
+    pub path: PathBuf,
+    pub registry: Option<String>,
+}
+
+impl Display for AdoptCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.registry {
+            Some(registry) => f.write_fmt(format_args!(
+                "tow adopt {} --source {}",
+                self.path.display(),
+                registry
+            )),
+            None => f.write_fmt(format_args!(
+                "{} (no known registry has it)",
+                self.path.display()
+            )),
+        }
+    }
+}
+
+/// Runs `<path> --version` and picks the first version looking word of its output.
+pub async fn detect_version(path: &Path) -> Option<String> {
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match timeout(VERSION_TIMEOUT, output).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(e)) => {
+            debug!("cannot run {} --version: {}", path.display(), e);
+            return None;
+        }
+        Err(_) => {
+            debug!("{} --version timed out", path.display());
+            return None;
+        }
+    };
+    parse_version(String::from_utf8_lossy(&output.stdout).as_ref())
+        .or_else(|| parse_version(String::from_utf8_lossy(&output.stderr).as_ref()))
+}
+
+/// Finds versions like `1.2.3` or `v0.10.0-rc1` in outputs like `tool 1.2.3 (abc123)`.
+pub fn parse_version(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .map(|x| x.trim_matches(|c: char| matches!(c, ',' | ';' | '(' | ')' | '"' | '\'')))
+        .find(|x| {
+            let number = x.strip_prefix('v').unwrap_or(x);
+            number.starts_with(|c: char| c.is_ascii_digit())
+                && number.contains('.')
+                && number
+                    .split('.')
+                    .next()
+                    .map(|x| x.chars().all(|c| c.is_ascii_digit()))
+                    .unwrap_or(false)
+        })
+        .map(|x| x.to_string())
+}
+
+/// Executable files in binaries_dir that no entry owns.
+pub fn find_untracked(
+    binaries_dir: &Path,
+    entries: &[&BinaryEntry],
+) -> Result<Vec<PathBuf>, TowError> {
+    if !binaries_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut untracked = Vec::new();
+    for entry in read_dir(binaries_dir).map_err(|e| TowError::io(binaries_dir, e))? {
+        let path = entry.map_err(|e| TowError::io(binaries_dir, e))?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .map(|x| x.starts_with('.'))
+            .unwrap_or(true);
+        if hidden || !is_executable(path.as_path()) || entries.iter().any(|x| x.path == path) {
+            continue;
+        }
+        untracked.push(path);
+    }
+    untracked.sort();
+    Ok(untracked)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("ripgrep 13.0.0").as_deref(), Some("13.0.0"));
+        assert_eq!(
+            parse_version("tool version v0.10.0-rc1, build abc").as_deref(),
+            Some("v0.10.0-rc1")
+        );
+        assert_eq!(
+            parse_version("jq-1.6\nfd (1.2.3)").as_deref(),
+            Some("1.2.3")
+        );
+        assert_eq!(parse_version("usage: tool [options]"), None);
+        assert_eq!(parse_version("uses 3 threads"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_detect_version() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let script = temp_dir.path().join("tool");
+        std::fs::write(&script, "#!/bin/sh\necho \"tool $1 2.4.1\"\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let version = tokio_test::block_on(detect_version(&script));
+        assert_eq!(version.as_deref(), Some("2.4.1"));
+        assert_eq!(
+            tokio_test::block_on(detect_version(&temp_dir.path().join("missing"))),
+            None
+        );
+    }
+}
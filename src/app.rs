@@ -1,41 +1,111 @@
-use crate::store::{AddBinaryCmd, BinaryEntry, RemoveBinaryCmd};
-use crate::{download, errors::TowError, local_store, store};
-use log::{error, info};
+use crate::adopt::{self, AdoptCandidate};
+use crate::cache::{self, CacheEntry, DownloadCache};
+use crate::config::{Config, StoreBackend, StoreConfig};
+use crate::credentials::Credentials;
+use crate::gc::{self, GcReport};
+use crate::http::HttpClient;
+use crate::registry::Registry;
+use crate::s3::{self, S3Registry};
+use crate::shim::{self, Shim};
+use crate::source::{self, Source};
+use crate::sqlite_store::SqliteTowStore;
+use crate::store::{AddBinaryCmd, BinaryEntry, Hashable, RemoveBinaryCmd, TowStore};
+use crate::verify::{self, Verification};
+use crate::{download, errors::TowError, files, local_store, registry, store};
+use futures_util::StreamExt;
+use log::{error, info, warn};
 use std::env;
+use std::fs::{copy, create_dir_all, read_link, remove_file};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const TOW_BINARIES_DIR_ENV: &str = "TOW_BINARIES_DIR";
-const TOW_STORE_DIR_ENV: &str = "TOW_STORE_DIR";
-const TOW_DATA_FOLDER_NAME: &str = "tow";
+pub const TOW_CACHE_FOLDER_NAME: &str = "cache";
+pub const TOW_DOWNLOADS_FOLDER_NAME: &str = "downloads";
 const DEFAULT_BINARY_VERSION: &str = "latest";
 
+/// How to resolve clashes with files already in binaries_dir.
+#[derive(Default)]
+pub struct InstallOptions {
+    /// overwrite files of other binaries or files not managed by tow
+    pub force: bool,
+    /// expose the binary as this command instead of the one guessed from the downloaded filename
+    pub command: Option<String>,
+}
+
 pub struct App<T: store::TowStore> {
     store: T,
+    cache: Option<DownloadCache>,
+    http: HttpClient,
+    offline: bool,
+    /// where files are fetched to before the store takes them
+    downloads_dir: PathBuf,
+    /// how many registry requests run at once
+    concurrency: usize,
+    /// words picking the asset of a release that has several
+    asset_preferences: Vec<String>,
+    /// expose commands as shims instead of links to the last installed version
+    shims: bool,
 }
 
-impl App<local_store::LocalTowStore> {
-    pub fn new_from_env() -> Result<Self, TowError> {
-        let binaries_dir = env::var(TOW_BINARIES_DIR_ENV).map_or_else(
-            |_| default_bin_dir(),
-            |x| Path::new(x.as_str()).to_path_buf(),
-        );
-        let store_dir = env::var(TOW_STORE_DIR_ENV).map_or_else(
-            |_| default_data_dir().join(TOW_DATA_FOLDER_NAME),
-            |x| Path::new(x.as_str()).to_path_buf(),
-        );
-        Self::new_from_dirs(binaries_dir, store_dir)
+impl App<Box<dyn TowStore>> {
+    /// confirm is asked before restoring a missing or unreadable store from its backup.
+    pub fn new_from_config(
+        config: &Config,
+        confirm: &dyn Fn(&str) -> bool,
+    ) -> Result<Self, TowError> {
+        let (binaries_dir, store_dir) = config.dirs()?;
+        Ok(
+            Self::new_from_config_with_dirs(config, binaries_dir, store_dir, confirm)?
+                .with_shims(config.shims),
+        )
     }
 
-    pub fn new_from_dirs(binaries_dir: PathBuf, store_dir: PathBuf) -> Result<Self, TowError> {
-        match local_store::LocalTowStore::load_or_create(
+    /// Like new_from_config, over other dirs, e.g. those of a project.
+    pub fn new_from_config_with_dirs(
+        config: &Config,
+        binaries_dir: PathBuf,
+        store_dir: PathBuf,
+        confirm: &dyn Fn(&str) -> bool,
+    ) -> Result<Self, TowError> {
+        let http = HttpClient::new(Credentials::load(config), &config.network)?
+            .with_s3(config.s3.clone())
+            .with_mirrors(config.mirrors.clone());
+        Ok(
+            Self::new_from_dirs(binaries_dir, store_dir, config, confirm)?
+                .with_http(http)
+                .with_offline(config.offline)
+                .with_concurrency(config.network.concurrency)
+                .with_asset_preferences(config.assets.prefer.clone()),
+        )
+    }
+
+    /// Uses the store and cache settings of config, not its dirs.
+    pub fn new_from_dirs(
+        binaries_dir: PathBuf,
+        store_dir: PathBuf,
+        config: &Config,
+        confirm: &dyn Fn(&str) -> bool,
+    ) -> Result<Self, TowError> {
+        match load_store(
             binaries_dir.as_path(),
             store_dir.as_path(),
+            &config.store,
+            confirm,
         ) {
             Err(e) => {
                 error!("error while loading or creating TowStore: {}", e);
                 Err(e)
             }
-            Ok(store) => Ok(App::new(store)),
+            Ok(store) => {
+                let cache_max_size = cache::parse_size(config.cache.max_size.as_str())?;
+                let cache = DownloadCache::load_or_create(
+                    store_dir.join(TOW_CACHE_FOLDER_NAME).as_path(),
+                    cache_max_size,
+                )?;
+                Ok(App::new(store)
+                    .with_cache(cache)
+                    .with_downloads_dir(store_dir.join(TOW_DOWNLOADS_FOLDER_NAME)))
+            }
         }
     }
 }
@@ -45,93 +115,621 @@ where
     T: store::TowStore,
 {
     pub fn new(store: T) -> Self {
-        App { store }
+        App {
+            store,
+            cache: None,
+            http: HttpClient::default(),
+            offline: false,
+            downloads_dir: env::temp_dir(),
+            concurrency: 1,
+            asset_preferences: Vec::new(),
+            shims: false,
+        }
     }
 
+    pub fn with_cache(mut self, cache: DownloadCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_http(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// In offline mode no network access is allowed, everything must come from the cache.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Kept apart from the system temp dir, so that `tow gc` can tell tow's leftovers apart.
+    pub fn with_downloads_dir(mut self, downloads_dir: PathBuf) -> Self {
+        self.downloads_dir = downloads_dir;
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_asset_preferences(mut self, asset_preferences: Vec<String>) -> Self {
+        self.asset_preferences = asset_preferences;
+        self
+    }
+
+    /// With shims every command runs the version pinned for the current dir, see shim.
+    pub fn with_shims(mut self, shims: bool) -> Self {
+        self.shims = shims;
+        self
+    }
+
+    /// Returns the entry of the installed binary.
     pub async fn install(
         &mut self,
-        url: &str,
+        source: &str,
         name: Option<&str>,
         version: Option<&str>,
-    ) -> Result<PathBuf, TowError> {
-        match url::Url::parse(url) {
+        options: &InstallOptions,
+    ) -> Result<BinaryEntry, TowError> {
+        let source = match Source::parse(source) {
             Err(e) => {
-                error!("Error parsing url: {}", e);
-                Err(e.into())
-            }
-            Ok(url) => {
-                info!("downloading url: {}", url);
-                match download::download_file(&url, env::temp_dir().as_path()).await {
-                    Err(e) => {
-                        error!("Error downloading url: {}", e);
-                        Err(e)
-                    }
-                    Ok(path) => {
-                        info!("downloaded to {}", path.display());
-                        let resolved_name = name
-                            .unwrap_or_else(|| path.file_name().and_then(|x| x.to_str()).unwrap());
-                        let resolved_version = version.unwrap_or(DEFAULT_BINARY_VERSION);
-                        self.store.add_binary(AddBinaryCmd::new(
-                            resolved_name.to_string(),
-                            resolved_version.to_string(),
-                            path.to_owned(),
-                            url.to_string(),
-                        ))?;
-                        Ok(path)
-                    }
+                error!("Error parsing source: {}", e);
+                return Err(e);
+            }
+            Ok(source) => source,
+        };
+        let fetched = match &source {
+            Source::Remote(url) => self.fetch(url).await,
+            Source::Local(path) => fetch_local(path, name, self.downloads_dir.as_path()),
+        };
+        match fetched {
+            Err(e) => {
+                error!("Error fetching {}: {}", source, e);
+                Err(e)
+            }
+            Ok(path) => {
+                let resolved_name = match (name, options.command.as_deref()) {
+                    (Some(name), _) | (None, Some(name)) => name.to_string(),
+                    (None, None) => store::command_name(
+                        path.file_name().and_then(|x| x.to_str()).ok_or_else(|| {
+                            TowError::config(&format!(
+                                "'{}' has no utf-8 file name, pass --name",
+                                path.display()
+                            ))
+                        })?,
+                    ),
+                };
+                let resolved_version = version.unwrap_or(DEFAULT_BINARY_VERSION);
+                let added = self.store.add_binary(
+                    AddBinaryCmd::new(
+                        resolved_name.to_owned(),
+                        resolved_version.to_string(),
+                        path.to_owned(),
+                        source.to_string(),
+                    )
+                    .with_file_name(options.command.to_owned())
+                    .with_force(options.force),
+                );
+                if let Err(e) = added {
+                    discard(path.as_path());
+                    return Err(e);
+                }
+                let be = self.entry(resolved_name.as_str(), resolved_version)?;
+                if self.shims && be.stored_path.is_some() {
+                    shim::write(&be, self.store.binaries_dir(), self.store.store_dir())?;
                 }
+                Ok(be)
             }
         }
     }
 
-    pub fn remove(&mut self, name: String, version: String) -> Result<(), TowError> {
+    /// Uninstalls the version of name and returns its entry.
+    pub fn remove(&mut self, name: String, version: String) -> Result<BinaryEntry, TowError> {
+        let be = self
+            .store
+            .list_binaries()
+            .into_iter()
+            .find(|x| x.name == name && x.version == version)
+            .cloned()
+            .ok_or_else(|| {
+                TowError::not_found(&format!("{}-{} is not in the store", name, version))
+            })?;
         let rm = RemoveBinaryCmd::new(name, version);
-        self.store.remove_binary(rm)
+        self.store.remove_binary(rm)?;
+        self.relink(be.path.as_path())?;
+        Ok(be)
+    }
+
+    /// Exposes a version left at path after a removal, as the link to the removed version is gone
+    /// with it: the shim or link at path points to the remaining version (the newest one unless
+    /// it still points to another), and a shim is removed with the last version.
+    fn relink(&self, path: &Path) -> Result<(), TowError> {
+        let is_shim = shim::is_shim(path);
+        match self.active_entry(path) {
+            Some(be) if self.shims || is_shim => {
+                shim::write(be, self.store.binaries_dir(), self.store.store_dir()).map(|_| ())
+            }
+            // a file that is neither a link nor a shim is not tow's to replace
+            Some(_) if path.exists() && read_link(path).is_err() => Ok(()),
+            Some(be) => match be.stored_path.as_deref() {
+                Some(stored_path) => files::link_atomically(stored_path, path),
+                None => Ok(()),
+            },
+            None if is_shim => files::remove_if_exists(path),
+            None => Ok(()),
+        }
+    }
+
+    /// The version the command at path runs where none is pinned: the one it links to, the
+    /// fallback of its shim, else the newest one.
+    fn active_entry(&self, path: &Path) -> Option<&BinaryEntry> {
+        let entries: Vec<&BinaryEntry> = self
+            .store
+            .list_binaries()
+            .into_iter()
+            .filter(|x| x.path == path && x.stored_path.is_some())
+            .collect();
+        let linked = read_link(path).ok();
+        let fallback = shim::read(path).map(|x| x.version);
+        entries
+            .iter()
+            .find(|x| linked.is_some() && x.stored_path == linked)
+            .or_else(|| {
+                entries
+                    .iter()
+                    .find(|x| Some(&x.version) == fallback.as_ref())
+            })
+            .or_else(|| {
+                entries
+                    .iter()
+                    .max_by(|a, b| registry::compare_versions(&a.version, &b.version))
+            })
+            .copied()
+    }
+
+    /// Writes a shim for every command with a stored binary, e.g. after turning shims on.
+    pub fn shims_rebuild(&mut self) -> Result<Vec<Shim>, TowError> {
+        let _lock = self.store.lock_store()?;
+        let mut shims = Vec::new();
+        for path in self.command_paths() {
+            if let Some(be) = self.active_entry(path.as_path()) {
+                shims.push(shim::write(
+                    be,
+                    self.store.binaries_dir(),
+                    self.store.store_dir(),
+                )?);
+            }
+        }
+        Ok(shims)
+    }
+
+    /// Replaces every shim with a link to the version it falls back to.
+    pub fn shims_remove(&mut self) -> Result<Vec<Shim>, TowError> {
+        let _lock = self.store.lock_store()?;
+        let mut removed = Vec::new();
+        for path in self.command_paths() {
+            let found = match shim::read(path.as_path()) {
+                Some(x) => x,
+                None => continue,
+            };
+            match self
+                .active_entry(path.as_path())
+                .and_then(|x| x.stored_path.as_deref())
+            {
+                Some(stored_path) => files::link_atomically(stored_path, path.as_path())?,
+                None => files::remove_if_exists(path.as_path())?,
+            }
+            removed.push(found);
+        }
+        Ok(removed)
+    }
+
+    /// Exposed paths of the installed binaries, each once.
+    fn command_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .store
+            .list_binaries()
+            .into_iter()
+            .filter(|x| x.stored_path.is_some())
+            .map(|x| x.path.to_owned())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
     }
 
     pub fn list(&self) -> Vec<&BinaryEntry> {
         self.store.list_binaries()
     }
-}
 
-#[cfg(target_os = "macos")]
-fn default_bin_dir() -> PathBuf {
-    dirs::home_dir()
-        .map(|x| x.join(".local").join("bin"))
-        .expect("cannot get user's home dir")
-}
+    /// Registers a binary installed by hand, keeping its content, so that it can be upgraded
+    /// from source. The version is detected from `--version` output if not given.
+    pub async fn adopt(
+        &mut self,
+        path: &Path,
+        source: &str,
+        name: Option<&str>,
+        version: Option<&str>,
+    ) -> Result<BinaryEntry, TowError> {
+        if self.store.list_binaries().iter().any(|x| x.path == path) {
+            return Err(TowError::conflict(&format!(
+                "{} is already managed by tow",
+                path.display()
+            )));
+        }
+        if !path.is_file() {
+            return Err(TowError::not_found(&format!(
+                "{} is not a file",
+                path.display()
+            )));
+        }
+        let version = match version {
+            Some(x) => x.to_string(),
+            None => adopt::detect_version(path).await.ok_or_else(|| {
+                TowError::config(&format!(
+                    "cannot detect the version of {}, pass --version",
+                    path.display()
+                ))
+            })?,
+        };
+        let name = match name {
+            Some(x) => x.to_string(),
+            None => path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .ok_or_else(|| {
+                    TowError::config(&format!(
+                        "'{}' has no utf-8 file name, pass --name",
+                        path.display()
+                    ))
+                })?
+                .to_string(),
+        };
+        // a copy is staged, so a failed adoption leaves the original file alone
+        let copied = fetch_local(path, None, self.downloads_dir.as_path())?;
+        // the command keeps the name of the adopted file, the link replacing the file itself
+        // is no clash
+        let file_name = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .map(str::to_string);
+        let replaces_itself = file_name
+            .as_deref()
+            .map(|x| self.store.binaries_dir().join(x) == path)
+            .unwrap_or(false);
+        let added = self.store.add_binary(
+            AddBinaryCmd::new(
+                name.to_owned(),
+                version.to_owned(),
+                copied.to_owned(),
+                source.to_string(),
+            )
+            .with_file_name(file_name)
+            .with_force(replaces_itself),
+        );
+        if let Err(e) = added {
+            discard(copied.as_path());
+            return Err(e);
+        }
+        info!("adopted {} from {}", path.display(), source);
+        self.entry(name.as_str(), version.as_str())
+    }
 
-#[cfg(target_os = "macos")]
-fn default_data_dir() -> PathBuf {
-    dirs::home_dir()
-        .map(|x| x.join(".local").join("share"))
-        .expect("cannot get user's home dir")
-}
+    fn entry(&self, name: &str, version: &str) -> Result<BinaryEntry, TowError> {
+        self.store
+            .list_binaries()
+            .into_iter()
+            .find(|x| x.name == name && x.version == version)
+            .cloned()
+            .ok_or_else(|| {
+                TowError::store(&format!("{} {} is missing after adding it", name, version))
+            })
+    }
+
+    /// Unmanaged executables in binaries_dir, with the first of registries that has them.
+    pub async fn adopt_candidates(
+        &mut self,
+        registries: &[String],
+    ) -> Result<Vec<AdoptCandidate>, TowError> {
+        let untracked = adopt::find_untracked(
+            self.store.binaries_dir(),
+            self.store.list_binaries().as_slice(),
+        )?;
+        // lookups run concurrently, registries are tried in order for each binary
+        let lookups: Vec<_> = futures_util::stream::iter(untracked)
+            .map(|path| async {
+                let name = path
+                    .file_name()
+                    .and_then(|x| x.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+                for registry in registries {
+                    match self.lookup_releases(registry, name.as_str()).await {
+                        Ok(releases) if !releases.is_empty() => {
+                            return (path, name, Some((registry, releases)))
+                        }
+                        Ok(_) => {}
+                        Err(e) => info!("{} is not in {}: {}", name, registry, e),
+                    }
+                }
+                (path, name, None)
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        let mut candidates = Vec::new();
+        for (path, name, found) in lookups {
+            if let (Some((registry, releases)), false, Some(cache)) =
+                (found.as_ref(), self.offline, self.cache.as_mut())
+            {
+                cache.put_releases(registry, name.as_str(), releases.to_owned())?;
+            }
+            candidates.push(AdoptCandidate {
+                path,
+                registry: found.map(|x| x.0.to_owned()),
+            });
+        }
+        Ok(candidates)
+    }
 
-#[cfg(target_os = "linux")]
-fn default_bin_dir() -> PathBuf {
-    dirs::executable_dir().expect("cannot get user's bin dir")
+    /// Re-hashes every installed binary against its recorded digest.
+    pub fn verify(&self) -> Vec<Verification> {
+        let mut entries = self.store.list_binaries();
+        entries.sort_by_key(|x| x.hash());
+        entries
+            .iter()
+            .flat_map(|x| verify::verify_entry(x, entries.as_slice()))
+            .collect()
+    }
+
+    /// Removes what nothing refers to anymore: version dirs, leftover downloads and temp files,
+    /// dangling links and cache entries not used for longer than cache_older_than.
+    /// A dry run only reports what would be removed.
+    pub fn gc(&mut self, dry_run: bool, cache_older_than: Duration) -> Result<GcReport, TowError> {
+        let _lock = self.store.lock_store()?;
+        let mut found = gc::find(
+            self.store.store_dir(),
+            self.store.binaries_dir(),
+            self.downloads_dir.as_path(),
+            self.store.list_binaries().as_slice(),
+            gc::DEFAULT_GRACE,
+        )?;
+        if let Some(cache) = self.cache.as_ref() {
+            found.extend(
+                cache
+                    .orphaned_blobs()?
+                    .into_iter()
+                    .map(|x| gc::Garbage::new(gc::GarbageKind::CacheBlob, x)),
+            );
+        }
+
+        let mut report = GcReport::default();
+        for garbage in found {
+            if dry_run {
+                report.files.push(garbage);
+                continue;
+            }
+            match gc::remove(&garbage) {
+                Err(e) => error!("cannot remove {}: {}", garbage.path.display(), e),
+                Ok(_) => report.files.push(garbage),
+            }
+        }
+        report.cache_entries = match self.cache.as_mut() {
+            None => Vec::new(),
+            Some(cache) if dry_run => cache.stale(cache_older_than).into_iter().cloned().collect(),
+            Some(cache) => cache.prune(cache_older_than)?,
+        };
+        Ok(report)
+    }
+
+    /// Installs the release of name from the registry, the latest one if no version is given.
+    pub async fn install_from_registry(
+        &mut self,
+        registry: &str,
+        name: &str,
+        version: Option<&str>,
+        options: &InstallOptions,
+    ) -> Result<BinaryEntry, TowError> {
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => self.releases(registry, name).await?.pop().ok_or_else(|| {
+                TowError::not_found(&format!("no releases of {} in {}", name, registry))
+            })?,
+        };
+        let url = self.release_url(registry, name, version.as_str()).await?;
+        self.install(url.as_str(), Some(name), Some(version.as_str()), options)
+            .await
+    }
+
+    /// Lists versions available in the registry, served from the cache in offline mode.
+    pub async fn releases(&mut self, registry: &str, name: &str) -> Result<Vec<String>, TowError> {
+        let releases = self.lookup_releases(registry, name).await?;
+        if let (false, Some(cache)) = (self.offline, self.cache.as_mut()) {
+            cache.put_releases(registry, name, releases.to_owned())?;
+        }
+        Ok(releases)
+    }
+
+    /// Like releases, without caching them.
+    async fn lookup_releases(&self, registry: &str, name: &str) -> Result<Vec<String>, TowError> {
+        if self.offline {
+            return self
+                .get_cache()?
+                .get_releases(registry, name)
+                .cloned()
+                .ok_or_else(|| {
+                    TowError::not_found(&format!(
+                        "offline mode: no cached releases of {} in {}",
+                        name, registry
+                    ))
+                });
+        }
+        self.get_registry(registry)?
+            .get_releases(name.to_string())
+            .await
+    }
+
+    async fn release_url(
+        &mut self,
+        registry: &str,
+        name: &str,
+        version: &str,
+    ) -> Result<String, TowError> {
+        if self.offline {
+            return self
+                .get_cache()?
+                .get_release_url(registry, name, version)
+                .cloned()
+                .ok_or_else(|| {
+                    TowError::not_found(&format!(
+                        "offline mode: no cached url of {} {} in {}",
+                        name, version, registry
+                    ))
+                });
+        }
+        let url = self
+            .get_registry(registry)?
+            .get_url(name.to_string(), version.to_string())
+            .await?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.put_release_url(registry, name, version, url.to_owned())?;
+        }
+        Ok(url)
+    }
+
+    fn get_registry(&self, registry: &str) -> Result<Box<dyn Registry + '_>, TowError> {
+        let url = url::Url::parse(registry)?;
+        match url.scheme() {
+            s3::SCHEME => Ok(Box::new(
+                S3Registry::new(&self.http, &url)?
+                    .with_asset_preferences(self.asset_preferences.to_owned()),
+            )),
+            scheme => Err(TowError::config(&format!(
+                "no registry supports '{}' urls",
+                scheme
+            ))),
+        }
+    }
+
+    pub fn cache_list(&self) -> Result<Vec<&CacheEntry>, TowError> {
+        Ok(self.get_cache()?.list())
+    }
+
+    pub fn cache_clean(&mut self) -> Result<Vec<CacheEntry>, TowError> {
+        self.get_cache_mut()?.clean()
+    }
+
+    pub fn cache_prune(&mut self, older_than: Duration) -> Result<Vec<CacheEntry>, TowError> {
+        self.get_cache_mut()?.prune(older_than)
+    }
+
+    /// Gets the file either from the download cache or from the url itself.
+    async fn fetch(&mut self, url: &url::Url) -> Result<PathBuf, TowError> {
+        let temp_dir = self.downloads_dir.to_owned();
+        create_dir_all(temp_dir.as_path()).map_err(|e| TowError::io(temp_dir.as_path(), e))?;
+        if let Some(cache) = self.cache.as_mut() {
+            if let Some(etag) = cache.get(url.as_str()).map(|x| x.etag.to_owned()) {
+                let fresh = match etag {
+                    _ if self.offline => true,
+                    None => true,
+                    Some(etag) => match download::fetch_etag(&self.http, url).await {
+                        Ok(current) => current.as_deref() == Some(etag.as_str()),
+                        Err(e) => {
+                            warn!("cannot revalidate cached {}, using it anyway: {}", url, e);
+                            true
+                        }
+                    },
+                };
+                if fresh {
+                    info!("using cached download of url: {}", url);
+                    return cache.restore(url.as_str(), temp_dir.as_path());
+                }
+            }
+        }
+
+        if self.offline {
+            return Err(TowError::not_found(&format!(
+                "offline mode: {} is not in the download cache",
+                url
+            )));
+        }
+
+        info!("downloading url: {}", url);
+        let download = download::download_file(&self.http, url, temp_dir.as_path()).await?;
+        info!("downloaded to {}", download.path.display());
+        if let Some(cache) = self.cache.as_mut() {
+            if let Err(e) = cache.insert(url.as_str(), download.etag.as_deref(), &download.path) {
+                warn!("cannot add {} to the download cache: {}", url, e);
+            }
+        }
+        Ok(download.path)
+    }
+
+    fn get_cache(&self) -> Result<&DownloadCache, TowError> {
+        self.cache
+            .as_ref()
+            .ok_or_else(|| TowError::config("download cache is not configured"))
+    }
+
+    fn get_cache_mut(&mut self) -> Result<&mut DownloadCache, TowError> {
+        self.cache
+            .as_mut()
+            .ok_or_else(|| TowError::config("download cache is not configured"))
+    }
 }
 
-#[cfg(target_os = "linux")]
-fn default_data_dir() -> PathBuf {
-    dirs::data_dir().expect("cannot get user's data dir")
+/// Copies the local file into temp_dir so that the store never moves the user's original.
+fn fetch_local(path: &Path, name: Option<&str>, temp_dir: &Path) -> Result<PathBuf, TowError> {
+    let file = source::resolve_local_file(path, name)?;
+    let file_name = file
+        .file_name()
+        .ok_or_else(|| TowError::config(&format!("'{}' has no file name", file.display())))?;
+    create_dir_all(temp_dir).map_err(|e| TowError::io(temp_dir, e))?;
+    let temp_path = temp_dir.join(file_name);
+    info!("copying {} to {}", file.display(), temp_path.display());
+    copy(file.as_path(), temp_path.as_path()).map_err(|e| TowError::io(file.as_path(), e))?;
+    Ok(temp_path)
 }
 
-#[cfg(target_os = "windows")]
-fn default_bin_dir() -> PathBuf {
-    panic!("no windows support yet, sorry!")
+/// Drops a fetched file the store did not take.
+fn discard(path: &Path) {
+    if path.is_file() {
+        if let Err(e) = remove_file(path) {
+            warn!("cannot remove {}: {}", path.display(), e);
+        }
+    }
 }
 
-#[cfg(target_os = "windows")]
-fn default_data_dir() -> PathBuf {
-    panic!("no windows support yet, sorry!")
+/// Loads the store of the backend, confirm is asked before restoring a backup.
+pub fn load_store(
+    binaries_dir: &Path,
+    store_dir: &Path,
+    store_config: &StoreConfig,
+    confirm: &dyn Fn(&str) -> bool,
+) -> Result<Box<dyn TowStore>, TowError> {
+    let lock_timeout = cache::parse_duration(store_config.lock_timeout.as_str())?;
+    Ok(match store_config.backend {
+        StoreBackend::Json => Box::new(local_store::LocalTowStore::load_or_create(
+            binaries_dir,
+            store_dir,
+            lock_timeout,
+            confirm,
+        )?),
+        StoreBackend::Sqlite => Box::new(SqliteTowStore::load_or_create(
+            binaries_dir,
+            store_dir,
+            lock_timeout,
+        )?),
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use mockito::mock;
+    use crate::lock::StoreLock;
+    use mockito::{mock, Matcher};
 
     #[test]
     fn test_install() {
@@ -155,13 +753,512 @@ mod test {
         // this is gets deleted once it goes out of scope
         let temp_dir = tempfile::tempdir().unwrap();
         let temp_path = temp_dir.path();
-        let mut app = App::new_from_dirs(temp_path.to_path_buf(), temp_path.to_path_buf()).unwrap();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
         assert!(!temp_path.join(filename).is_file());
 
-        tokio_test::block_on(app.install(url.as_str(), None, None)).unwrap();
+        tokio_test::block_on(app.install(url.as_str(), None, None, &InstallOptions::default()))
+            .unwrap();
         assert!(temp_path.join(filename).is_file());
     }
 
+    #[test]
+    fn test_install_strips_asset_suffixes() {
+        let endpoint = "/release";
+        let filename = "tool-v1.2.3-x86_64-unknown-linux-musl";
+
+        let _m = mock("GET", endpoint)
+            .with_status(200)
+            .with_header(
+                "content-disposition",
+                &format!("attachment; filename={}", filename),
+            )
+            .with_body("tool")
+            .create();
+        let url = format!("{}{}", &mockito::server_url(), endpoint);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        tokio_test::block_on(app.install(url.as_str(), None, Some("1"), &Default::default()))
+            .unwrap();
+        assert!(temp_path.join("tool").is_file());
+        assert!(!temp_path.join(filename).exists());
+        let be = app.list()[0].to_owned();
+        assert_eq!(be.name, "tool");
+        assert_eq!(be.asset.as_deref(), Some(filename));
+
+        let options = InstallOptions {
+            command: Some("tl".to_string()),
+            ..Default::default()
+        };
+        tokio_test::block_on(app.install(url.as_str(), None, Some("2"), &options)).unwrap();
+        assert!(temp_path.join("tl").is_file());
+        assert!(app.list().iter().any(|x| x.name == "tl"));
+    }
+
+    #[test]
+    fn test_gc() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = source_dir.path().join("tool");
+        std::fs::write(&source, "tool").unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        let source = source.to_str().unwrap();
+        let options = InstallOptions::default();
+        tokio_test::block_on(app.install(source, None, Some("1"), &options)).unwrap();
+        // rejected by the store, the copy must not be left behind
+        assert!(tokio_test::block_on(app.install(source, None, Some("1"), &options)).is_err());
+        let downloads = temp_path.join(TOW_DOWNLOADS_FOLDER_NAME);
+        assert_eq!(std::fs::read_dir(&downloads).unwrap().count(), 0);
+
+        let orphan = temp_path
+            .join(local_store::STORED_BINARIES_DIR)
+            .join("tool")
+            .join("0");
+        create_dir_all(&orphan).unwrap();
+        std::fs::write(orphan.join("tool"), "old tool").unwrap();
+
+        let report = app.gc(true, Duration::from_secs(0)).unwrap();
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.reclaimed(), 8);
+        assert!(orphan.is_dir());
+
+        let report = app.gc(false, Duration::from_secs(0)).unwrap();
+        assert_eq!(report.files.len(), 1);
+        assert!(!orphan.exists());
+        assert!(temp_path.join("tool").is_file());
+        assert!(app
+            .gc(false, Duration::from_secs(0))
+            .unwrap()
+            .files
+            .is_empty());
+    }
+
+    #[test]
+    fn test_install_from_cache() {
+        let endpoint = "/cached";
+        let filename = "cached.txt";
+
+        // prepare mockito, the file can be downloaded only once
+        let _m = mock("GET", endpoint)
+            .with_status(200)
+            .with_header(
+                "content-disposition",
+                &format!("attachment; filename={}", filename),
+            )
+            .with_body("Hello cache!")
+            .expect(1)
+            .create();
+
+        let url = format!("{}{}", &mockito::server_url(), endpoint);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        tokio_test::block_on(app.install(
+            url.as_str(),
+            None,
+            Some("1"),
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(app.cache_list().unwrap().len(), 1);
+        // same file name, a different binary must not silently take it over
+        let err = tokio_test::block_on(app.install(
+            url.as_str(),
+            Some("other"),
+            Some("1"),
+            &InstallOptions::default(),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("belongs to"));
+        let options = InstallOptions {
+            command: Some("other.txt".to_string()),
+            ..Default::default()
+        };
+        tokio_test::block_on(app.install(url.as_str(), Some("other"), Some("1"), &options))
+            .unwrap();
+        assert_eq!(app.list().len(), 2);
+        assert!(temp_path.join("other.txt").is_file());
+        _m.assert();
+    }
+
+    #[test]
+    fn test_install_offline() {
+        let endpoint = "/offline";
+        let filename = "offline.txt";
+
+        let _m = mock("GET", endpoint)
+            .with_status(200)
+            .with_header(
+                "content-disposition",
+                &format!("attachment; filename={}", filename),
+            )
+            .with_body("Hello offline!")
+            .expect(1)
+            .create();
+
+        let url = format!("{}{}", &mockito::server_url(), endpoint);
+        let missing_url = format!("{}/missing", &mockito::server_url());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+        tokio_test::block_on(app.install(
+            url.as_str(),
+            None,
+            Some("1"),
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+
+        let mut app = app.with_offline(true);
+        let err = tokio_test::block_on(app.install(
+            missing_url.as_str(),
+            None,
+            None,
+            &InstallOptions::default(),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("not in the download cache"));
+        tokio_test::block_on(app.install(
+            url.as_str(),
+            None,
+            Some("2"),
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(app.list().len(), 2);
+        _m.assert();
+    }
+
+    #[test]
+    fn test_install_local() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let artifact = artifacts_dir.path().join("tool");
+        std::fs::write(&artifact, "binary").unwrap();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        let file_url = url::Url::from_file_path(&artifact).unwrap();
+        tokio_test::block_on(app.install(
+            file_url.as_str(),
+            None,
+            Some("1"),
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+        tokio_test::block_on(app.install(
+            artifacts_dir.path().to_str().unwrap(),
+            Some("tool"),
+            Some("2"),
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+
+        // the original must stay where it was
+        assert!(artifact.is_file());
+        assert!(temp_path.join("tool").is_file());
+        let absolute = artifact.canonicalize().unwrap();
+        let sources: Vec<&str> = app.list().iter().map(|x| x.source.as_str()).collect();
+        assert!(sources.contains(&absolute.to_str().unwrap()));
+        assert!(sources.contains(&absolute.parent().unwrap().to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_verify_versions() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let artifact = artifacts_dir.path().join("tool");
+        std::fs::write(&artifact, "binary").unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut app = App::new_from_dirs(
+            temp_dir.path().join("bin"),
+            temp_dir.path().to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+        for version in ["1.0.0", "2.0.0"] {
+            tokio_test::block_on(app.install(
+                artifact.to_str().unwrap(),
+                None,
+                Some(version),
+                &InstallOptions::default(),
+            ))
+            .unwrap();
+        }
+        let verifications = app.verify();
+        // two stored files and the one link they share
+        assert_eq!(verifications.len(), 3);
+        assert!(verifications.iter().all(|x| x.is_ok()));
+    }
+
+    #[test]
+    fn test_remove_relinks_remaining_version() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let artifact = artifacts_dir.path().join("tool");
+        std::fs::write(&artifact, "binary").unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut app = App::new_from_dirs(
+            temp_dir.path().join("bin"),
+            temp_dir.path().to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+        let mut installed = Vec::new();
+        for version in ["1.0.0", "2.0.0"] {
+            installed.push(
+                tokio_test::block_on(app.install(
+                    artifact.to_str().unwrap(),
+                    None,
+                    Some(version),
+                    &InstallOptions::default(),
+                ))
+                .unwrap(),
+            );
+        }
+        let path = installed[0].path.to_owned();
+
+        app.remove("tool".to_string(), "2.0.0".to_string()).unwrap();
+        assert_eq!(
+            read_link(&path).ok(),
+            installed[0].stored_path,
+            "the remaining version is linked"
+        );
+        assert!(app.verify().iter().all(|x| x.is_ok()));
+
+        app.remove("tool".to_string(), "1.0.0".to_string()).unwrap();
+        assert!(path.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_shims() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let artifact = artifacts_dir.path().join("tool");
+        std::fs::write(&artifact, "binary").unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let binaries_dir = temp_dir.path().join("bin");
+        let mut app = App::new_from_dirs(
+            binaries_dir.to_owned(),
+            temp_dir.path().to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap()
+        .with_shims(true);
+        let install = |app: &mut App<_>, version| {
+            tokio_test::block_on(app.install(
+                artifact.to_str().unwrap(),
+                None,
+                Some(version),
+                &InstallOptions::default(),
+            ))
+            .unwrap()
+        };
+        install(&mut app, "1.0.0");
+        install(&mut app, "2.0.0");
+        let path = binaries_dir.join("tool");
+        assert_eq!(shim::read(&path).unwrap().version, "2.0.0");
+        // both versions are reachable through the shim
+        assert!(app.verify().iter().all(|x| x.is_ok()));
+
+        app.remove("tool".to_string(), "2.0.0".to_string()).unwrap();
+        assert_eq!(shim::read(&path).unwrap().version, "1.0.0");
+
+        let removed = app.shims_remove().unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(read_link(&path).is_ok());
+        assert_eq!(app.shims_rebuild().unwrap()[0].version, "1.0.0");
+
+        app.remove("tool".to_string(), "1.0.0".to_string()).unwrap();
+        assert!(path.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_install_from_s3_registry() {
+        let _versions = mock("GET", "/bucket")
+            .match_query(Matcher::UrlEncoded("prefix".into(), "tools/s3tool/".into()))
+            .with_body(
+                "<ListBucketResult>\
+                 <CommonPrefixes><Prefix>tools/s3tool/1.0/</Prefix></CommonPrefixes>\
+                 <CommonPrefixes><Prefix>tools/s3tool/2.0/</Prefix></CommonPrefixes>\
+                 </ListBucketResult>",
+            )
+            .expect(1)
+            .create();
+        let _assets = mock("GET", "/bucket")
+            .match_query(Matcher::UrlEncoded("prefix".into(), "tools/s3tool/2.0/".into()))
+            .with_body("<ListBucketResult><Contents><Key>tools/s3tool/2.0/s3tool</Key></Contents></ListBucketResult>")
+            .expect(1)
+            .create();
+        let _object = mock("GET", "/bucket/tools/s3tool/2.0/s3tool")
+            .with_body("binary")
+            .expect(1)
+            .create();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let http = HttpClient::default().with_s3(crate::config::S3Config {
+            endpoint: Some(mockito::server_url()),
+            access_key_id: Some("minio".to_string()),
+            secret_access_key: Some("minio123".to_string()),
+            ..Default::default()
+        });
+        let mut app = App::new_from_dirs(
+            temp_path.to_path_buf(),
+            temp_path.to_path_buf(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap()
+        .with_http(http);
+
+        tokio_test::block_on(app.install_from_registry(
+            "s3://bucket/tools",
+            "s3tool",
+            None,
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+        let installed = app.list();
+        assert_eq!(installed[0].version, "2.0");
+        assert_eq!(installed[0].source, "s3://bucket/tools/s3tool/2.0/s3tool");
+
+        // metadata and the file itself must come from the cache when offline
+        let mut app = app.with_offline(true);
+        app.remove("s3tool".to_string(), "2.0".to_string()).unwrap();
+        tokio_test::block_on(app.install_from_registry(
+            "s3://bucket/tools",
+            "s3tool",
+            None,
+            &InstallOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(app.list().len(), 1);
+    }
+
+    #[test]
+    fn test_adopt() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let binaries_dir = temp_path.join("bin");
+        std::fs::create_dir_all(&binaries_dir).unwrap();
+        let hand_installed = binaries_dir.join("adoptee");
+        std::fs::write(&hand_installed, "by hand").unwrap();
+        let mut app = App::new_from_dirs(
+            binaries_dir.to_owned(),
+            temp_path.join("store"),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        let candidates = tokio_test::block_on(app.adopt_candidates(&[])).unwrap();
+        // not executable yet
+        assert!(candidates.is_empty());
+        crate::files::make_executable(&hand_installed).unwrap();
+        let candidates = tokio_test::block_on(app.adopt_candidates(&[])).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(candidates[0].registry.is_none());
+
+        tokio_test::block_on(app.adopt(
+            &hand_installed,
+            "https://example.com/adoptee",
+            None,
+            Some("v1.0.0"),
+        ))
+        .unwrap();
+        let be = app.list()[0].clone();
+        assert_eq!(be.name, "adoptee");
+        assert_eq!(be.source, "https://example.com/adoptee");
+        assert_eq!(be.path, hand_installed);
+        assert_eq!(std::fs::read_to_string(&hand_installed).unwrap(), "by hand");
+        assert!(tokio_test::block_on(app.adopt_candidates(&[]))
+            .unwrap()
+            .is_empty());
+        assert!(tokio_test::block_on(app.adopt(
+            &hand_installed,
+            "https://example.com/adoptee",
+            None,
+            Some("v1.0.0"),
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_adopt_keeps_file_name() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let binaries_dir = temp_path.join("bin");
+        std::fs::create_dir_all(&binaries_dir).unwrap();
+        // an unmanaged file under the name guessed from the adopted one
+        let unmanaged = binaries_dir.join("tool");
+        std::fs::write(&unmanaged, "unmanaged").unwrap();
+        let adopted = binaries_dir.join("tool-linux");
+        std::fs::write(&adopted, "by hand").unwrap();
+        let mut app = App::new_from_dirs(
+            binaries_dir.to_owned(),
+            temp_path.join("store"),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+
+        let be = tokio_test::block_on(app.adopt(
+            &adopted,
+            "https://example.com/tool",
+            Some("tool"),
+            Some("v1.0.0"),
+        ))
+        .unwrap();
+        assert_eq!(be.path, adopted);
+        assert_eq!(std::fs::read_to_string(&adopted).unwrap(), "by hand");
+        assert_eq!(std::fs::read_to_string(&unmanaged).unwrap(), "unmanaged");
+    }
+
     #[test]
     fn test_list() {
         let app = App::new(DummyStore::new_with_count(0));
@@ -201,12 +1298,24 @@ mod test {
         }
 
         fn remove_binary(&mut self, _: RemoveBinaryCmd) -> Result<(), TowError> {
-            if self.bes.len() == 0 {
-                return Err(TowError::new("no more binaries"));
+            if self.bes.is_empty() {
+                return Err(TowError::not_found("no more binaries"));
             }
             self.bes.pop();
             Ok(())
         }
+
+        fn binaries_dir(&self) -> &Path {
+            Path::new("")
+        }
+
+        fn store_dir(&self) -> &Path {
+            Path::new("")
+        }
+
+        fn lock_store(&mut self) -> Result<StoreLock, TowError> {
+            Err(TowError::store("the dummy store cannot be locked"))
+        }
     }
 
     impl DummyStore {
@@ -224,6 +1333,9 @@ mod test {
                 version: "version".to_string(),
                 path: PathBuf::new(),
                 source: "source".to_string(),
+                stored_path: None,
+                sha256: None,
+                asset: None,
             }
         }
     }
@@ -0,0 +1,470 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::errors::TowError;
+use crate::files;
+
+const CACHE_INDEX_FILENAME: &str = "index.json";
+const CACHE_BLOBS_DIR: &str = "blobs";
+/// in the format of parse_size
+pub const DEFAULT_CACHE_MAX_SIZE: &str = "1G";
+
+/// Content-addressed cache of downloaded files.
+///
+/// Files are stored once under their SHA-256 digest and indexed by the url they were
+/// downloaded from, together with the ETag the server sent for them.
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: CacheIndex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    /// release metadata of registries, keyed by registry url and binary name
+    #[serde(default)]
+    releases: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    release_urls: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub sha256: String,
+    pub filename: String,
+    pub size: u64,
+    pub created: u64,
+    pub last_access: u64,
+}
+
+impl Display for CacheEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} {} {} {}",
+            self.sha256, self.size, self.filename, self.url
+        ))
+    }
+}
+
+impl DownloadCache {
+    pub fn load_or_create(dir: &Path, max_size: u64) -> Result<Self, TowError> {
+        let blobs_dir = dir.join(CACHE_BLOBS_DIR);
+        create_dir_all(&blobs_dir).map_err(|e| TowError::io(&blobs_dir, e))?;
+        let index_path = dir.join(CACHE_INDEX_FILENAME);
+        let index = if index_path.is_file() {
+            serde_json::from_reader(
+                File::open(&index_path).map_err(|e| TowError::io(&index_path, e))?,
+            )?
+        } else {
+            CacheIndex::default()
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_size,
+            index,
+        })
+    }
+
+    pub fn get(&self, url: &str) -> Option<&CacheEntry> {
+        self.index
+            .entries
+            .get(url)
+            .filter(|e| self.blob_path(&e.sha256).is_file())
+    }
+
+    pub fn get_by_sha256(&self, sha256: &str) -> Option<&CacheEntry> {
+        self.index
+            .entries
+            .values()
+            .find(|e| e.sha256 == sha256 && self.blob_path(&e.sha256).is_file())
+    }
+
+    /// Copies the cached file for the url into dest_dir and marks it as recently used.
+    pub fn restore(&mut self, url: &str, dest_dir: &Path) -> Result<PathBuf, TowError> {
+        let entry = self
+            .index
+            .entries
+            .get_mut(url)
+            .ok_or_else(|| TowError::not_found(&format!("{} is not in the cache", url)))?;
+        entry.last_access = now();
+        let dest = dest_dir.join(&entry.filename);
+        copy(self.dir.join(CACHE_BLOBS_DIR).join(&entry.sha256), &dest)
+            .map_err(|e| TowError::io(&dest, e))?;
+        debug!("restored {} from cache to {}", url, dest.display());
+        self.save()?;
+        Ok(dest)
+    }
+
+    /// Adds a copy of the downloaded file to the cache and evicts old entries if needed.
+    pub fn insert(
+        &mut self,
+        url: &str,
+        etag: Option<&str>,
+        path: &Path,
+    ) -> Result<CacheEntry, TowError> {
+        let filename = path.file_name().and_then(|x| x.to_str()).ok_or_else(|| {
+            TowError::config(&format!("'{}' has no utf-8 file name", path.display()))
+        })?;
+        let sha256 = sha256_file(path)?;
+        let blob = self.blob_path(&sha256);
+        if !blob.is_file() {
+            copy(path, &blob).map_err(|e| TowError::io(&blob, e))?;
+        }
+        let timestamp = now();
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: etag.map(|x| x.to_string()),
+            sha256,
+            filename: filename.to_string(),
+            size: blob.metadata()?.len(),
+            created: timestamp,
+            last_access: timestamp,
+        };
+        self.index.entries.insert(url.to_string(), entry.clone());
+        self.evict(self.max_size)?;
+        self.save()?;
+        info!("cached {} as {}", url, entry.sha256);
+        Ok(entry)
+    }
+
+    pub fn get_releases(&self, registry: &str, name: &str) -> Option<&Vec<String>> {
+        self.index.releases.get(&releases_key(registry, name))
+    }
+
+    pub fn put_releases(
+        &mut self,
+        registry: &str,
+        name: &str,
+        releases: Vec<String>,
+    ) -> Result<(), TowError> {
+        self.index
+            .releases
+            .insert(releases_key(registry, name), releases);
+        self.save()
+    }
+
+    pub fn get_release_url(&self, registry: &str, name: &str, version: &str) -> Option<&String> {
+        self.index
+            .release_urls
+            .get(&release_url_key(registry, name, version))
+    }
+
+    pub fn put_release_url(
+        &mut self,
+        registry: &str,
+        name: &str,
+        version: &str,
+        url: String,
+    ) -> Result<(), TowError> {
+        self.index
+            .release_urls
+            .insert(release_url_key(registry, name, version), url);
+        self.save()
+    }
+
+    pub fn list(&self) -> Vec<&CacheEntry> {
+        let mut entries: Vec<&CacheEntry> = self.index.entries.values().collect();
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        entries
+    }
+
+    pub fn clean(&mut self) -> Result<Vec<CacheEntry>, TowError> {
+        let removed = self.index.entries.drain().map(|x| x.1).collect();
+        let blobs_dir = self.dir.join(CACHE_BLOBS_DIR);
+        remove_dir_all(&blobs_dir)
+            .and_then(|_| create_dir_all(&blobs_dir))
+            .map_err(|e| TowError::io(&blobs_dir, e))?;
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Entries that were not used for longer than older_than.
+    pub fn stale(&self, older_than: Duration) -> Vec<&CacheEntry> {
+        let threshold = now().saturating_sub(older_than.as_secs());
+        let mut entries: Vec<&CacheEntry> = self
+            .index
+            .entries
+            .values()
+            .filter(|e| e.last_access < threshold)
+            .collect();
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        entries
+    }
+
+    /// Removes entries that were not used for longer than older_than.
+    pub fn prune(&mut self, older_than: Duration) -> Result<Vec<CacheEntry>, TowError> {
+        let urls: Vec<String> = self
+            .stale(older_than)
+            .iter()
+            .map(|e| e.url.to_owned())
+            .collect();
+        let removed = self.remove_entries(&urls)?;
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Blobs no entry refers to, left behind by an interrupted insert or removal.
+    pub fn orphaned_blobs(&self) -> Result<Vec<PathBuf>, TowError> {
+        let mut orphans = Vec::new();
+        let blobs_dir = self.dir.join(CACHE_BLOBS_DIR);
+        for entry in read_dir(&blobs_dir).map_err(|e| TowError::io(&blobs_dir, e))? {
+            let path = entry.map_err(|e| TowError::io(&blobs_dir, e))?.path();
+            let referenced = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .map(|x| self.get_by_sha256(x).is_some())
+                .unwrap_or(false);
+            if !referenced {
+                orphans.push(path);
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        let mut seen = Vec::new();
+        let mut size = 0;
+        for e in self.index.entries.values() {
+            if !seen.contains(&&e.sha256) {
+                seen.push(&e.sha256);
+                size += e.size;
+            }
+        }
+        size
+    }
+
+    /// Drops the least recently used entries until the cache fits into max_size.
+    fn evict(&mut self, max_size: u64) -> Result<(), TowError> {
+        let mut entries: Vec<(String, u64)> = self
+            .index
+            .entries
+            .values()
+            .map(|e| (e.url.to_owned(), e.last_access))
+            .collect();
+        entries.sort_by_key(|x| x.1);
+        let mut lru = entries.into_iter();
+        while self.total_size() > max_size {
+            match lru.next() {
+                None => break,
+                Some((url, _)) => {
+                    warn!("cache size limit exceeded, evicting {}", url);
+                    self.remove_entries(&[url])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_entries(&mut self, urls: &[String]) -> Result<Vec<CacheEntry>, TowError> {
+        let mut removed = Vec::new();
+        for url in urls {
+            if let Some(entry) = self.index.entries.remove(url) {
+                removed.push(entry);
+            }
+        }
+        // blobs can be shared between urls, remove only the unreferenced ones
+        for entry in &removed {
+            if self.get_by_sha256(&entry.sha256).is_none() {
+                match remove_file(self.blob_path(&entry.sha256)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<(), TowError> {
+        files::write_atomically(
+            self.dir.join(CACHE_INDEX_FILENAME).as_path(),
+            &serde_json::to_vec_pretty(&self.index)?,
+        )
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(CACHE_BLOBS_DIR).join(sha256)
+    }
+}
+
+fn releases_key(registry: &str, name: &str) -> String {
+    format!("{}#{}", registry, name)
+}
+
+fn release_url_key(registry: &str, name: &str, version: &str) -> String {
+    format!("{}#{}@{}", registry, name, version)
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, TowError> {
+    let mut file = File::open(path).map_err(|e| TowError::io(path, e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| TowError::io(path, e))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Parses durations like "30s", "15m", "12h", "7d" or "2w". Plain numbers are seconds, durations
+/// over u64::MAX seconds are refused.
+pub fn parse_duration(s: &str) -> Result<Duration, TowError> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        Some('d') => (&s[..s.len() - 1], 24 * 60 * 60),
+        Some('w') => (&s[..s.len() - 1], 7 * 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| TowError::config(&format!("cannot parse duration from '{}'", s)))
+}
+
+/// Parses sizes like "512", "100K", "500M" or "2G" into bytes, sizes over u64::MAX are refused.
+pub fn parse_size(s: &str) -> Result<u64, TowError> {
+    let s = s.trim();
+    let (number, multiplier) = match s.to_ascii_uppercase().chars().last() {
+        Some('K') => (&s[..s.len() - 1], 1024),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(multiplier))
+        .ok_or_else(|| TowError::config(&format!("cannot parse size from '{}'", s)))
+}
+
+/// Formats bytes the way parse_size reads them, with one decimal above 1K.
+pub fn format_size(bytes: u64) -> String {
+    let units = [("G", 1024 * 1024 * 1024), ("M", 1024 * 1024), ("K", 1024)];
+    match units.iter().find(|x| bytes >= x.1) {
+        Some((unit, size)) => format!("{:.1}{}", bytes as f64 / *size as f64, unit),
+        None => bytes.to_string(),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    const URL: &str = "https://example.com/file.txt";
+
+    #[test]
+    fn test_insert_and_restore() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut cache = DownloadCache::load_or_create(&temp_path.join("cache"), u64::MAX).unwrap();
+        let file = fake_file(temp_path, "file.txt", "hello");
+
+        let entry = cache.insert(URL, Some("\"abc\""), &file).unwrap();
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.etag.as_deref(), Some("\"abc\""));
+        assert!(cache.get(URL).is_some());
+        assert!(cache.get_by_sha256(&entry.sha256).is_some());
+
+        // index must survive reloading
+        let mut cache = DownloadCache::load_or_create(&temp_path.join("cache"), u64::MAX).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let restored = cache.restore(URL, dest.path()).unwrap();
+        assert_eq!(restored, dest.path().join("file.txt"));
+        assert_eq!(sha256_file(&restored).unwrap(), entry.sha256);
+    }
+
+    #[test]
+    fn test_clean_and_prune() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut cache = DownloadCache::load_or_create(&temp_path.join("cache"), u64::MAX).unwrap();
+        let file = fake_file(temp_path, "file.txt", "hello");
+        cache.insert(URL, None, &file).unwrap();
+
+        assert_eq!(cache.prune(Duration::from_secs(60)).unwrap().len(), 0);
+        assert_eq!(cache.list().len(), 1);
+        cache.index.entries.get_mut(URL).unwrap().last_access = 0;
+        assert_eq!(cache.prune(Duration::from_secs(60)).unwrap().len(), 1);
+        assert!(cache.get(URL).is_none());
+
+        cache.insert(URL, None, &file).unwrap();
+        assert_eq!(cache.clean().unwrap().len(), 1);
+        assert_eq!(cache.list().len(), 0);
+        assert_eq!(cache.total_size(), 0);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut cache = DownloadCache::load_or_create(&temp_path.join("cache"), 8).unwrap();
+        let first = fake_file(temp_path, "first.txt", "12345");
+        let second = fake_file(temp_path, "second.txt", "67890");
+
+        cache
+            .insert("https://example.com/first", None, &first)
+            .unwrap();
+        cache
+            .index
+            .entries
+            .get_mut("https://example.com/first")
+            .unwrap()
+            .last_access = 0;
+        cache
+            .insert("https://example.com/second", None, &second)
+            .unwrap();
+
+        assert!(cache.get("https://example.com/first").is_none());
+        assert!(cache.get("https://example.com/second").is_some());
+        assert_eq!(cache.total_size(), 5);
+    }
+
+    #[test]
+    fn test_parse_duration_and_size() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604800));
+        assert!(parse_duration("week").is_err());
+        assert!(parse_duration("99999999999999999d").is_err());
+        assert_eq!(
+            parse_duration("18446744073709551615s").unwrap().as_secs(),
+            u64::MAX
+        );
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("2k").unwrap(), 2048);
+        assert_eq!(parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(format_size(512), "512");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0G");
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("99999999999999999G").is_err());
+    }
+
+    fn fake_file(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        File::create(&path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+        path
+    }
+}
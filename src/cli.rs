@@ -1,40 +1,653 @@
-use crate::{app::App, store};
+use crate::app::{self, App, InstallOptions};
+use crate::cache;
+use crate::config::{self, Config};
+use crate::doctor::{self, Severity};
+use crate::errors::TowError;
+use crate::gc;
+use crate::local_store::LocalTowStore;
+use crate::logs;
+use crate::output::{self, EntryRecord, OutputFormat, Record, ReleaseRecord};
+use crate::profile;
+use crate::project::{self, Project};
+use crate::shim;
 use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    /// Forbid all network access, install only from the download cache
+    #[clap(long, global = true)]
+    offline: bool,
+
+    /// Disable TLS certificate verification
+    #[clap(long, global = true)]
+    insecure: bool,
+
+    /// Answer yes to all questions, e.g. whether to restore the store from its backup
+    #[clap(short, long, global = true)]
+    yes: bool,
+
+    /// Output format: plain, table or json (one document with a stable schema, for scripts)
+    #[clap(long, global = true, default_value = "plain")]
+    output: OutputFormat,
+
+    /// Use the binaries and store dirs of this profile (overrides TOW_PROFILE), see `tow profile list`
+    #[clap(long, global = true)]
+    profile: Option<String>,
+
+    /// Show more messages on stderr, -v for info, -vv for debug, -vvv for trace (overrides TOW_LOG)
+    #[clap(short, long, global = true, parse(from_occurrences))]
+    verbose: usize,
+
+    /// Show no messages and no progress bars on stderr, only errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Install { url: String },
+    Install {
+        /// url, `s3://` url, `file://` url or a local file or directory path; without it,
+        /// name is installed from the default registry of the config
+        #[clap(required_unless_present = "name")]
+        source: Option<String>,
+        /// treat source as a registry (e.g. s3://bucket/prefix) and install name from it
+        #[clap(long, requires = "name")]
+        registry: bool,
+        #[clap(long)]
+        name: Option<String>,
+        /// defaults to the latest release when installing from a registry
+        #[clap(long)]
+        version: Option<String>,
+        /// overwrite a file of another binary or a file not managed by tow
+        #[clap(long)]
+        force: bool,
+        /// install as this command, by default the downloaded filename without
+        /// its version, os and architecture parts
+        #[clap(long = "as", alias = "rename-to", value_name = "COMMAND-NAME")]
+        command: Option<String>,
+    },
+    List,
+    Uninstall {
+        name: String,
+        version: String,
+    },
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommands,
+    },
+    /// List versions available in a registry, e.g. s3://bucket/prefix
+    Releases {
+        registry: String,
+        name: String,
+    },
+    Store {
+        #[clap(subcommand)]
+        command: StoreCommands,
+    },
+    /// Register a binary installed by hand, so that tow can upgrade it
+    Adopt {
+        #[clap(required_unless_present = "scan")]
+        path: Option<String>,
+        /// url or registry to upgrade the binary from
+        #[clap(long, required_unless_present = "scan")]
+        source: Option<String>,
+        #[clap(long)]
+        name: Option<String>,
+        /// detected from `<path> --version` if not given
+        #[clap(long)]
+        version: Option<String>,
+        /// list unmanaged binaries in binaries_dir, looking them up in the configured registries
+        #[clap(long, conflicts_with_all = &["path", "source"])]
+        scan: bool,
+    },
+    /// Re-hash installed binaries and report modified or missing files
+    Verify,
+    /// Check the environment: PATH, permissions, the store and shadowed binaries
+    Doctor,
+    /// Show and change settings of the config file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Install the tools of the tow.toml of the current project into its .tow dir
+    Sync,
+    /// Run a command with the tools of the current project first on PATH, syncing them first
+    Exec {
+        /// e.g. `tow exec -- tool --version`
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Print shell exports that put the tools of the current project on PATH, for direnv
+    Env,
+    /// Manage profiles, separate sets of binaries each with its own binaries and store dirs
+    Profile {
+        #[clap(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Manage the shims that run the version pinned in the nearest .tow-version or tow.toml
+    Shims {
+        #[clap(subcommand)]
+        command: ShimsCommands,
+    },
+    /// Run the version of a binary pinned for the current dir, called by its shim
+    #[clap(hide = true)]
+    ShimExec {
+        #[clap(long, parse(from_os_str))]
+        binaries_dir: PathBuf,
+        #[clap(long, parse(from_os_str))]
+        store_dir: PathBuf,
+        name: String,
+        /// run where no version is pinned
+        version: String,
+        #[clap(last = true, allow_invalid_utf8 = true, parse(from_os_str))]
+        args: Vec<OsString>,
+    },
+    /// Remove unreferenced versions, leftover downloads, dangling links and old cache entries
+    Gc {
+        /// only report what would be removed
+        #[clap(long)]
+        dry_run: bool,
+        /// e.g. 30m, 12h, 7d, defaults to 30d
+        #[clap(long)]
+        cache_older_than: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum StoreCommands {
+    /// Replace the store with a backup, by default the newest valid one
+    RestoreBackup {
+        /// 0 is the newest backup, 1 the one before it and so on
+        #[clap(long)]
+        backup: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the effective value of a key, e.g. network.https_proxy
+    Get { key: String },
+    /// Set a key in the config file, e.g. `tow config set network.concurrency 8`
+    Set { key: String, value: String },
+    /// List the effective settings and where each one comes from
     List,
-    Uninstall { name: String, version: String },
+    /// Print the path of the config file
+    Path,
 }
 
-pub async fn run_cli<T: store::TowStore>(mut app: App<T>) {
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List the profiles, the active one is marked with *
+    List,
+    /// Add a profile to the config file and create its dirs
+    Create {
+        name: String,
+        /// defaults to `bin` in the store dir of the profile
+        #[clap(long)]
+        binaries_dir: Option<PathBuf>,
+        /// defaults to `profiles/<name>` in the default store dir
+        #[clap(long)]
+        store_dir: Option<PathBuf>,
+    },
+    /// Remove a profile from the config file
+    Delete {
+        name: String,
+        /// also uninstall its binaries and delete the files of tow in its store dir
+        #[clap(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ShimsCommands {
+    /// Write a shim for every installed command, e.g. after setting `shims = true`
+    Rebuild,
+    /// Replace the shims with links to the versions they fall back to
+    Remove,
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    List,
+    Clean,
+    Prune {
+        /// e.g. 30m, 12h, 7d
+        #[clap(long)]
+        older_than: String,
+    },
+}
+
+/// Runs the command, printing errors with a hint and exiting with the code of their category.
+pub async fn run_cli() {
     let cli = Cli::parse();
+    let result = match init(&cli) {
+        Ok(config) => run(cli, config).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        if let Some(hint) = e.hint() {
+            eprintln!("hint: {}", hint);
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+/// Loads the config with the flags on top and sets up logging. The log file goes to the
+/// store dir, it is skipped if the store dir is unknown.
+fn init(cli: &Cli) -> Result<Config, TowError> {
+    let mut config = Config::load()?;
+    if cli.offline {
+        config.offline = true;
+        config.set_by_flag("offline", "--offline");
+    }
+    if cli.insecure {
+        config.network.insecure = true;
+        config.set_by_flag("network.insecure", "--insecure");
+    }
+    if let Some(profile) = cli.profile.as_ref() {
+        config.profile = Some(profile.to_owned());
+        config.set_by_flag("profile", "--profile");
+    }
+
+    let stderr_level = logs::stderr_level(cli.verbose, cli.quiet)?;
+    let log_dir = config
+        .dirs()
+        .ok()
+        .map(|(_, store_dir)| store_dir.join(logs::LOG_FOLDER_NAME));
+    let file = match (logs::file_level()?, log_dir.as_ref()) {
+        (Some(level), Some(dir)) => Some((dir.as_path(), level)),
+        _ => None,
+    };
+    logs::init(stderr_level, file);
+    Ok(config)
+}
+
+async fn run(cli: Cli, config: Config) -> Result<(), TowError> {
+    let format = cli.output;
+    let assume_yes = cli.yes;
+    let ask = |question: &str| assume_yes || confirm(question);
 
-    // You can check for the existence of subcommands, and if found use their
-    // matches just as you would the top level app
     match &cli.command {
-        Commands::Install { url } => {
-            app.install(url, None, None)
-                .await
-                .expect("could not install binary; see previous errors");
+        Commands::Config { command } => run_config(format, &config, command)?,
+        // runs for every command of a shim, so it only reads the store of the shim
+        Commands::ShimExec {
+            binaries_dir,
+            store_dir,
+            name,
+            version,
+            args,
+        } => {
+            let store = app::load_store(binaries_dir, store_dir, &config.store, &|_| false)?;
+            let entries = store.list_binaries();
+            let be = shim::resolve(&entries, name, version, env::current_dir()?.as_path())?;
+            shim::exec(be, args)?;
+        }
+        Commands::Env => {
+            let project = Project::find(env::current_dir()?.as_path())?;
+            print!("{}", project::env_exports(&project));
+        }
+        Commands::Sync | Commands::Exec { .. } => {
+            let project = Project::find(env::current_dir()?.as_path())?;
+            let mut app = App::new_from_config_with_dirs(
+                &config,
+                project.bin_dir(),
+                project.store_dir(),
+                &ask,
+            )?;
+            let report = project
+                .sync(&mut app, config.default_registry.as_deref())
+                .await?;
+            drop(app);
+            if let Commands::Exec { command } = &cli.command {
+                return exec(&project, command);
+            }
+            match format {
+                OutputFormat::Json => output::print_json(json!({
+                    "installed": report.installed.iter().map(EntryRecord::from).collect::<Vec<_>>(),
+                    "removed": report.removed.iter().map(EntryRecord::from).collect::<Vec<_>>(),
+                }))?,
+                _ => {
+                    let installed: Vec<EntryRecord> =
+                        report.installed.iter().map(EntryRecord::from).collect();
+                    let removed: Vec<EntryRecord> =
+                        report.removed.iter().map(EntryRecord::from).collect();
+                    print_installed(format, &installed)?;
+                    if format == OutputFormat::Table {
+                        println!();
+                    }
+                    print_removed(format, &removed)?;
+                }
+            }
+        }
+        Commands::Profile { command } => run_profile(format, config, command, &ask)?,
+        // the store may be unreadable, so restore it before loading it in the app
+        Commands::Store { command } => match command {
+            StoreCommands::RestoreBackup { backup } => {
+                let (_, store_dir) = config.dirs()?;
+                let lock_timeout = cache::parse_duration(config.store.lock_timeout.as_str())?;
+                let restored =
+                    LocalTowStore::restore_backup(store_dir.as_path(), *backup, lock_timeout)?;
+                match format {
+                    OutputFormat::Json => output::print_json(json!({ "restored": restored }))?,
+                    _ => println!("restored the store from {}", restored.display()),
+                }
+            }
+        },
+        // the store may be unreadable, which is one of the things to report
+        Commands::Doctor => {
+            let (binaries_dir, store_dir) = config.dirs()?;
+            let checks = doctor::run(binaries_dir.as_path(), store_dir.as_path(), &config.store);
+            output::print(format, "checks", &checks)?;
+            let failed = checks
+                .iter()
+                .filter(|x| x.severity == Severity::Error)
+                .count();
+            if failed > 0 {
+                return Err(TowError::verification(&format!("{} checks failed", failed)));
+            }
+        }
+        Commands::Install {
+            source,
+            registry,
+            name,
+            version,
+            force,
+            command,
+        } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            let options = InstallOptions {
+                force: *force,
+                command: command.to_owned(),
+            };
+            let installed = match (source, config.default_registry.as_ref()) {
+                (Some(source), _) if !*registry => {
+                    app.install(source, name.as_deref(), version.as_deref(), &options)
+                        .await
+                }
+                (Some(registry), _) | (None, Some(registry)) => {
+                    app.install_from_registry(
+                        registry,
+                        name.as_deref().unwrap_or_default(),
+                        version.as_deref(),
+                        &options,
+                    )
+                    .await
+                }
+                (None, None) => Err(TowError::config(
+                    "no source given and no default_registry in the config",
+                )),
+            };
+            output::print(format, "installed", &[EntryRecord::from(&installed?)])?;
         }
         Commands::List => {
-            for be in app.list() {
-                println!("{}", be)
+            let app = App::new_from_config(&config, &ask)?;
+            let records: Vec<EntryRecord> = app.list().into_iter().map(EntryRecord::from).collect();
+            let profile = config.active_profile();
+            match format {
+                OutputFormat::Json => output::print_json(json!({
+                    "profile": profile,
+                    "binaries": records,
+                }))?,
+                _ => {
+                    println!("profile: {}", profile);
+                    output::print(format, "binaries", &records)?;
+                }
             }
         }
         Commands::Uninstall { name, version } => {
-            app.remove(name.to_string(), version.to_string())
-                .expect("could not uninstall binary; see previous errors");
+            let mut app = App::new_from_config(&config, &ask)?;
+            let removed = app.remove(name.to_string(), version.to_string())?;
+            print_removed(format, &[EntryRecord::from(&removed)])?;
         }
+        Commands::Releases { registry, name } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            let records: Vec<ReleaseRecord> = app
+                .releases(registry, name)
+                .await?
+                .into_iter()
+                .map(|version| ReleaseRecord { version })
+                .collect();
+            output::print(format, "releases", &records)?;
+        }
+        Commands::Adopt { scan: true, .. } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            let mut registries = config.registries.to_owned();
+            if let Some(registry) = config.default_registry.as_ref() {
+                if !registries.contains(registry) {
+                    registries.push(registry.to_owned());
+                }
+            }
+            let candidates = app.adopt_candidates(&registries).await?;
+            output::print(format, "candidates", &candidates)?;
+        }
+        Commands::Adopt {
+            path,
+            source,
+            name,
+            version,
+            ..
+        } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            let adopted = app
+                .adopt(
+                    Path::new(path.as_deref().unwrap_or_default()),
+                    source.as_deref().unwrap_or_default(),
+                    name.as_deref(),
+                    version.as_deref(),
+                )
+                .await?;
+            output::print(format, "adopted", &[EntryRecord::from(&adopted)])?;
+        }
+        Commands::Verify => {
+            let app = App::new_from_config(&config, &ask)?;
+            let verifications = app.verify();
+            output::print(format, "files", &verifications)?;
+            let failed = verifications.iter().filter(|x| !x.is_ok()).count();
+            if failed > 0 {
+                return Err(TowError::verification(&format!(
+                    "{} of {} files failed verification",
+                    failed,
+                    verifications.len()
+                )));
+            }
+        }
+        Commands::Gc {
+            dry_run,
+            cache_older_than,
+        } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            let cache_older_than = match cache_older_than {
+                None => gc::DEFAULT_CACHE_MAX_AGE,
+                Some(x) => cache::parse_duration(x)?,
+            };
+            let report = app.gc(*dry_run, cache_older_than)?;
+            let (verb, total) = match dry_run {
+                true => ("would remove", "would reclaim"),
+                false => ("removed", "reclaimed"),
+            };
+            match format {
+                OutputFormat::Json => output::print_json(json!({
+                    "dry_run": dry_run,
+                    "files": report.files,
+                    "cache_entries": report.cache_entries,
+                    "reclaimed": report.reclaimed(),
+                }))?,
+                OutputFormat::Table => {
+                    print!("{}", output::table(&report.files));
+                    println!();
+                    print!("{}", output::table(&report.cache_entries));
+                    println!();
+                    println!("{} {}", total, cache::format_size(report.reclaimed()));
+                }
+                OutputFormat::Plain => {
+                    for garbage in &report.files {
+                        println!("{} {}", verb, garbage)
+                    }
+                    for ce in &report.cache_entries {
+                        println!("{} cache entry {}", verb, ce)
+                    }
+                    println!("{} {}", total, cache::format_size(report.reclaimed()));
+                }
+            }
+        }
+        Commands::Shims { command } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            match command {
+                ShimsCommands::Rebuild => {
+                    output::print(format, "shims", &app.shims_rebuild()?)?;
+                }
+                ShimsCommands::Remove => {
+                    print_removed(format, &app.shims_remove()?)?;
+                }
+            }
+        }
+        Commands::Cache { command } => {
+            let mut app = App::new_from_config(&config, &ask)?;
+            match command {
+                CacheCommands::List => {
+                    output::print(format, "cache_entries", &app.cache_list()?)?;
+                }
+                CacheCommands::Clean => {
+                    print_removed(format, &app.cache_clean()?)?;
+                }
+                CacheCommands::Prune { older_than } => {
+                    let older_than = cache::parse_duration(older_than)?;
+                    print_removed(format, &app.cache_prune(older_than)?)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_config(
+    format: OutputFormat,
+    config: &Config,
+    command: &ConfigCommands,
+) -> Result<(), TowError> {
+    match command {
+        ConfigCommands::Get { key } => {
+            let setting = config.get(key)?;
+            match format {
+                OutputFormat::Plain => println!("{}", setting.raw_value()),
+                _ => output::print(format, "settings", &[setting])?,
+            }
+        }
+        ConfigCommands::Set { key, value } => {
+            let path = config_path()?;
+            config::set(path.as_path(), key, value)?;
+        }
+        ConfigCommands::List => output::print(format, "settings", &config.settings()?)?,
+        ConfigCommands::Path => println!("{}", config_path()?.display()),
+    }
+    Ok(())
+}
+
+fn run_profile(
+    format: OutputFormat,
+    mut config: Config,
+    command: &ProfileCommands,
+    confirm: &dyn Fn(&str) -> bool,
+) -> Result<(), TowError> {
+    match command {
+        ProfileCommands::List => output::print(format, "profiles", &profile::list(&config)?)?,
+        ProfileCommands::Create {
+            name,
+            binaries_dir,
+            store_dir,
+        } => {
+            let created = profile::create(
+                config_path()?.as_path(),
+                &mut config,
+                name,
+                binaries_dir.as_deref(),
+                store_dir.as_deref(),
+            )?;
+            output::print(format, "created", &[created])?;
+        }
+        ProfileCommands::Delete { name, purge } => {
+            let deleted =
+                profile::delete(config_path()?.as_path(), &config, name, *purge, confirm)?;
+            match format {
+                OutputFormat::Plain => println!("removed profile {}", deleted.name),
+                _ => output::print(format, "removed", &[deleted])?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs command with the bin dir of the project first on PATH and exits with its exit code.
+fn exec(project: &Project, command: &[String]) -> Result<(), TowError> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| TowError::config("no command to run"))?;
+    let status = process::Command::new(program)
+        .args(args)
+        .env("PATH", project.path_var()?)
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                TowError::not_found(&format!("command not found: {}", program))
+            }
+            _ => TowError::io(Path::new(program), e),
+        })?;
+    match status.code() {
+        Some(0) => Ok(()),
+        // killed by a signal has no code
+        code => process::exit(code.unwrap_or(1)),
+    }
+}
+
+fn config_path() -> Result<PathBuf, TowError> {
+    config::config_path().ok_or_else(|| {
+        TowError::config("cannot find a config dir on this platform, set TOW_CONFIG")
+    })
+}
+
+fn print_installed<T: Record>(format: OutputFormat, records: &[T]) -> Result<(), TowError> {
+    match format {
+        OutputFormat::Plain => {
+            for record in records {
+                println!("installed {}", record)
+            }
+            Ok(())
+        }
+        _ => output::print(format, "installed", records),
+    }
+}
+
+fn print_removed<T: Record>(format: OutputFormat, records: &[T]) -> Result<(), TowError> {
+    match format {
+        OutputFormat::Plain => {
+            for record in records {
+                println!("removed {}", record)
+            }
+            Ok(())
+        }
+        _ => output::print(format, "removed", records),
+    }
+}
+
+/// Asks on stderr, anything but y/yes (including no terminal to answer) means no.
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
     }
 }
@@ -0,0 +1,894 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+
+use crate::cache;
+use crate::errors::TowError;
+use crate::registry;
+
+const TOW_CONFIG_ENV: &str = "TOW_CONFIG";
+const TOW_OFFLINE_ENV: &str = "TOW_OFFLINE";
+const TOW_SHIMS_ENV: &str = "TOW_SHIMS";
+const TOW_PROFILE_ENV: &str = "TOW_PROFILE";
+const TOW_BINARIES_DIR_ENV: &str = "TOW_BINARIES_DIR";
+const TOW_STORE_DIR_ENV: &str = "TOW_STORE_DIR";
+const TOW_CACHE_MAX_SIZE_ENV: &str = "TOW_CACHE_MAX_SIZE";
+const TOW_STORE_BACKEND_ENV: &str = "TOW_STORE_BACKEND";
+const TOW_LOCK_TIMEOUT_ENV: &str = "TOW_LOCK_TIMEOUT";
+const TOW_CONCURRENCY_ENV: &str = "TOW_CONCURRENCY";
+const TOW_DEFAULT_REGISTRY_ENV: &str = "TOW_DEFAULT_REGISTRY";
+const TOW_ASSET_PREFER_ENV: &str = "TOW_ASSET_PREFER";
+const GITHUB_TOKEN_ENV: &str = "GITHUB_TOKEN";
+const GITLAB_TOKEN_ENV: &str = "GITLAB_TOKEN";
+const TOW_CA_CERT_ENV: &str = "TOW_CA_CERT";
+const TOW_INSECURE_ENV: &str = "TOW_INSECURE";
+const TOW_S3_ENDPOINT_ENV: &str = "TOW_S3_ENDPOINT";
+const AWS_ENDPOINT_URL_ENVS: [&str; 2] = ["AWS_ENDPOINT_URL_S3", "AWS_ENDPOINT_URL"];
+const AWS_REGION_ENVS: [&str; 2] = ["AWS_REGION", "AWS_DEFAULT_REGION"];
+const AWS_ACCESS_KEY_ID_ENV: &str = "AWS_ACCESS_KEY_ID";
+const AWS_SECRET_ACCESS_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+const AWS_SESSION_TOKEN_ENV: &str = "AWS_SESSION_TOKEN";
+const HTTPS_PROXY_ENVS: [&str; 2] = ["HTTPS_PROXY", "https_proxy"];
+const HTTP_PROXY_ENVS: [&str; 2] = ["HTTP_PROXY", "http_proxy"];
+const NO_PROXY_ENVS: [&str; 2] = ["NO_PROXY", "no_proxy"];
+const TOW_CONFIG_FOLDER_NAME: &str = "tow";
+const TOW_CONFIG_FILENAME: &str = "config.toml";
+const TOW_DATA_FOLDER_NAME: &str = "tow";
+const TOW_PROFILES_FOLDER_NAME: &str = "profiles";
+const PROFILE_BIN_FOLDER_NAME: &str = "bin";
+/// profile of the `[dirs]` settings
+pub const DEFAULT_PROFILE: &str = "default";
+/// values of these keys are never printed
+const SECRET_KEYS: [&str; 4] = ["token", "password", "secret_access_key", "session_token"];
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub dirs: DirsConfig,
+    /// active profile, `default` uses `[dirs]`
+    pub profile: Option<String>,
+    /// dirs of the other profiles, e.g. `[profiles.work]`
+    pub profiles: BTreeMap<String, DirsConfig>,
+    pub cache: CacheConfig,
+    /// keyed by host, e.g. `[credentials."github.com"]`
+    pub credentials: HashMap<String, HostCredentials>,
+    pub tokens: TokensConfig,
+    pub network: NetworkConfig,
+    pub s3: S3Config,
+    /// url rewrite rules, e.g. for environments that cannot reach github.com
+    pub mirrors: Vec<MirrorConfig>,
+    pub store: StoreConfig,
+    /// registry used by `tow install --name <name>` when no source is given
+    pub default_registry: Option<String>,
+    /// registries searched by `tow adopt --scan`, e.g. `s3://bucket/tools`
+    pub registries: Vec<String>,
+    pub assets: AssetsConfig,
+    /// forbid all network access, install only from the download cache
+    pub offline: bool,
+    /// expose commands as shims that run the version pinned for the current dir
+    pub shims: bool,
+    /// where each setting that is not a default comes from, by key
+    #[serde(skip)]
+    pub origins: BTreeMap<String, Origin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct DirsConfig {
+    /// where binaries are installed, ~/.local/bin by default
+    pub binaries: Option<PathBuf>,
+    /// where the store, cache and logs are kept, ~/.local/share/tow by default
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// size limit of the download cache, e.g. `500M`
+    pub max_size: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size: cache::DEFAULT_CACHE_MAX_SIZE.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct TokensConfig {
+    /// sent to github.com, api.github.com and raw.githubusercontent.com
+    pub github: Option<String>,
+    /// sent to gitlab.com
+    pub gitlab: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub https_proxy: Option<String>,
+    pub http_proxy: Option<String>,
+    /// comma separated hosts, domains or CIDRs that bypass the proxy
+    pub no_proxy: Option<String>,
+    /// extra PEM file (or bundle) with CA certificates to trust
+    pub ca_cert: Option<PathBuf>,
+    /// disables TLS certificate verification, use only as a last resort
+    pub insecure: bool,
+    /// how many requests run at once, e.g. registry lookups of `tow adopt --scan`
+    pub concurrency: usize,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            https_proxy: None,
+            http_proxy: None,
+            no_proxy: None,
+            ca_cert: None,
+            insecure: false,
+            concurrency: 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StoreConfig {
+    pub backend: StoreBackend,
+    /// how long to wait for another tow process to release the store, e.g. `2m`
+    pub lock_timeout: String,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::default(),
+            lock_timeout: "30s".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// towstore.json
+    #[default]
+    Json,
+    /// towstore.sqlite3, imports towstore.json when created
+    Sqlite,
+}
+
+impl std::str::FromStr for StoreBackend {
+    type Err = TowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(TowError::config(&format!("unknown store backend '{}'", s))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AssetsConfig {
+    /// words looked for in asset names when a release has several, the first ones weigh more;
+    /// the os and architecture of this machine by default
+    pub prefer: Vec<String>,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        Self {
+            prefer: registry::default_asset_preferences(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct HostCredentials {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MirrorConfig {
+    /// urls starting with the prefix are rewritten
+    pub prefix: String,
+    /// replacements for the prefix, tried in order before the original url
+    pub urls: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct S3Config {
+    /// e.g. `http://localhost:9000` for MinIO, defaults to AWS
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+}
+
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field(
+                "secret_access_key",
+                &self.secret_access_key.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+impl fmt::Debug for HostCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostCredentials")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl fmt::Debug for TokensConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokensConfig")
+            .field("github", &self.github.as_ref().map(|_| "<redacted>"))
+            .field("gitlab", &self.gitlab.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Layer a setting comes from, each one overrides the ones before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "file {}", path.display()),
+            Origin::Env(name) => write!(f, "env {}", name),
+            Origin::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+impl Serialize for Origin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Effective value of a config key, as shown by `tow config list`.
+#[derive(Serialize, Debug)]
+pub struct Setting {
+    pub key: String,
+    pub value: toml::Value,
+    pub origin: Origin,
+}
+
+impl Setting {
+    /// Strings without quotes, other values as in the config file.
+    pub fn raw_value(&self) -> String {
+        match &self.value {
+            toml::Value::String(x) => x.to_owned(),
+            x => x.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}  # {}", self.key, self.value, self.origin)
+    }
+}
+
+impl Config {
+    /// Layers the defaults, the config file if it exists and env vars, the cli applies its
+    /// flags on top.
+    pub fn load() -> Result<Self, TowError> {
+        let mut config = match config_path() {
+            Some(path) if path.is_file() => Self::load_file(path.as_path())?,
+            _ => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn load_file(path: &Path) -> Result<Self, TowError> {
+        let content = read_to_string(path).map_err(|e| TowError::io(path, e))?;
+        let mut config = Self::parse(content.as_str())?;
+        for (key, _) in leaves(&parse_document(content.as_str())?) {
+            config.origins.insert(key, Origin::File(path.to_path_buf()));
+        }
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), TowError> {
+        if let Some(x) = self.env("offline", &[TOW_OFFLINE_ENV]) {
+            self.offline = is_truthy(x.as_str());
+        }
+        if let Some(x) = self.env("shims", &[TOW_SHIMS_ENV]) {
+            self.shims = is_truthy(x.as_str());
+        }
+        if let Some(x) = self.env("profile", &[TOW_PROFILE_ENV]) {
+            self.profile = Some(x);
+        }
+        if let Some(x) = self.env("dirs.binaries", &[TOW_BINARIES_DIR_ENV]) {
+            self.dirs.binaries = Some(PathBuf::from(x));
+        }
+        if let Some(x) = self.env("dirs.store", &[TOW_STORE_DIR_ENV]) {
+            self.dirs.store = Some(PathBuf::from(x));
+        }
+        if let Some(x) = self.env("cache.max_size", &[TOW_CACHE_MAX_SIZE_ENV]) {
+            self.cache.max_size = x;
+        }
+        if let Some(x) = self.env("store.backend", &[TOW_STORE_BACKEND_ENV]) {
+            self.store.backend = x.parse()?;
+        }
+        if let Some(x) = self.env("store.lock_timeout", &[TOW_LOCK_TIMEOUT_ENV]) {
+            self.store.lock_timeout = x;
+        }
+        if let Some(x) = self.env("tokens.github", &[GITHUB_TOKEN_ENV]) {
+            self.tokens.github = Some(x);
+        }
+        if let Some(x) = self.env("tokens.gitlab", &[GITLAB_TOKEN_ENV]) {
+            self.tokens.gitlab = Some(x);
+        }
+        if let Some(x) = self.env("default_registry", &[TOW_DEFAULT_REGISTRY_ENV]) {
+            self.default_registry = Some(x);
+        }
+        if let Some(x) = self.env("assets.prefer", &[TOW_ASSET_PREFER_ENV]) {
+            self.assets.prefer = split_list(x.as_str());
+        }
+
+        if let Some(x) = self.env("network.https_proxy", &HTTPS_PROXY_ENVS) {
+            self.network.https_proxy = Some(x);
+        }
+        if let Some(x) = self.env("network.http_proxy", &HTTP_PROXY_ENVS) {
+            self.network.http_proxy = Some(x);
+        }
+        if let Some(x) = self.env("network.no_proxy", &NO_PROXY_ENVS) {
+            self.network.no_proxy = Some(x);
+        }
+        if let Some(x) = self.env("network.ca_cert", &[TOW_CA_CERT_ENV]) {
+            self.network.ca_cert = Some(PathBuf::from(x));
+        }
+        if let Some(x) = self.env("network.insecure", &[TOW_INSECURE_ENV]) {
+            self.network.insecure = is_truthy(x.as_str());
+        }
+        if let Some(x) = self.env("network.concurrency", &[TOW_CONCURRENCY_ENV]) {
+            self.network.concurrency = x.parse().map_err(|_| {
+                TowError::config(&format!(
+                    "{} must be a number, got '{}'",
+                    TOW_CONCURRENCY_ENV, x
+                ))
+            })?;
+        }
+
+        if let Some(x) = self
+            .env("s3.endpoint", &[TOW_S3_ENDPOINT_ENV])
+            .or_else(|| self.env("s3.endpoint", &AWS_ENDPOINT_URL_ENVS))
+        {
+            self.s3.endpoint = Some(x);
+        }
+        if let Some(x) = self.env("s3.region", &AWS_REGION_ENVS) {
+            self.s3.region = Some(x);
+        }
+        if let Some(x) = self.env("s3.access_key_id", &[AWS_ACCESS_KEY_ID_ENV]) {
+            self.s3.access_key_id = Some(x);
+        }
+        if let Some(x) = self.env("s3.secret_access_key", &[AWS_SECRET_ACCESS_KEY_ENV]) {
+            self.s3.secret_access_key = Some(x);
+        }
+        if let Some(x) = self.env("s3.session_token", &[AWS_SESSION_TOKEN_ENV]) {
+            self.s3.session_token = Some(x);
+        }
+        Ok(())
+    }
+
+    /// Value of the first non-empty env var of names, recorded as the origin of key.
+    fn env(&mut self, key: &str, names: &[&str]) -> Option<String> {
+        let (name, value) = names.iter().find_map(|name| {
+            env::var(name)
+                .ok()
+                .filter(|x| !x.is_empty())
+                .map(|x| (name, x))
+        })?;
+        self.origins
+            .insert(key.to_string(), Origin::Env(name.to_string()));
+        Some(value)
+    }
+
+    /// Records that a cli flag set key.
+    pub fn set_by_flag(&mut self, key: &str, flag: &str) {
+        self.origins
+            .insert(key.to_string(), Origin::Flag(flag.to_string()));
+    }
+
+    pub fn parse(content: &str) -> Result<Self, TowError> {
+        toml::from_str(content).map_err(|e| TowError::config_with("cannot parse config file", e))
+    }
+
+    pub fn active_profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// binaries_dir and store_dir of the active profile.
+    pub fn dirs(&self) -> Result<(PathBuf, PathBuf), TowError> {
+        self.profile_dirs(self.active_profile())
+    }
+
+    /// binaries_dir and store_dir of profile, configured or the defaults: the platform dirs
+    /// for the default profile, `<default store dir>/profiles/<name>` (with a `bin` dir inside)
+    /// for the others.
+    pub fn profile_dirs(&self, profile: &str) -> Result<(PathBuf, PathBuf), TowError> {
+        if profile == DEFAULT_PROFILE {
+            let binaries_dir = match &self.dirs.binaries {
+                Some(x) => x.to_owned(),
+                None => default_bin_dir()?,
+            };
+            let store_dir = match &self.dirs.store {
+                Some(x) => x.to_owned(),
+                None => default_store_dir()?,
+            };
+            return Ok((binaries_dir, store_dir));
+        }
+        let dirs = self.profiles.get(profile).ok_or_else(|| {
+            TowError::not_found(&format!(
+                "profile '{}' does not exist, see `tow profile list`",
+                profile
+            ))
+        })?;
+        let store_dir = match &dirs.store {
+            Some(x) => x.to_owned(),
+            None => default_profile_store_dir(profile)?,
+        };
+        let binaries_dir = match &dirs.binaries {
+            Some(x) => x.to_owned(),
+            None => store_dir.join(PROFILE_BIN_FOLDER_NAME),
+        };
+        Ok((binaries_dir, store_dir))
+    }
+
+    /// Every setting with its effective value, secrets redacted. Unset optional settings
+    /// are left out, the dirs are shown even when they are the platform defaults.
+    pub fn settings(&self) -> Result<Vec<Setting>, TowError> {
+        let mut document = toml::Value::try_from(self)
+            .map_err(|e| TowError::config_with("cannot serialize config", e))?;
+        if let (Ok((binaries_dir, store_dir)), Some(dirs)) = (
+            self.dirs(),
+            document.get_mut("dirs").and_then(|x| x.as_table_mut()),
+        ) {
+            for (name, dir) in [("binaries", binaries_dir), ("store", store_dir)] {
+                dirs.entry(name)
+                    .or_insert_with(|| toml::Value::String(dir.display().to_string()));
+            }
+        }
+        Ok(leaves(&document)
+            .into_iter()
+            .map(|(key, value)| Setting {
+                origin: self.origins.get(&key).cloned().unwrap_or(Origin::Default),
+                value: match is_secret(key.as_str()) {
+                    true => toml::Value::String("<redacted>".to_string()),
+                    false => value,
+                },
+                key,
+            })
+            .collect())
+    }
+
+    /// Effective setting of key, e.g. `network.https_proxy` or `credentials."github.com".token`.
+    pub fn get(&self, key: &str) -> Result<Setting, TowError> {
+        let key = join_key(split_key(key)?.as_slice());
+        self.settings()?
+            .into_iter()
+            .find(|x| x.key == key)
+            .ok_or_else(|| TowError::not_found(&format!("config key '{}' is not set", key)))
+    }
+}
+
+/// Sets key to value in the config file at path, creating it if needed. The value is read as a
+/// toml value (e.g. `true`, `4`, `["a", "b"]`), a string or a comma separated list, whichever
+/// the key accepts. Comments in the file are not kept.
+pub fn set(path: &Path, key: &str, value: &str) -> Result<(), TowError> {
+    let segments = split_key(key)?;
+    let key = join_key(segments.as_slice());
+    let document = match path.is_file() {
+        true => parse_document(
+            read_to_string(path)
+                .map_err(|e| TowError::io(path, e))?
+                .as_str(),
+        )?,
+        false => toml::Value::Table(Default::default()),
+    };
+    let candidates = [
+        parse_document(format!("value = {}", value).as_str())
+            .ok()
+            .and_then(|x| x.get("value").cloned()),
+        Some(toml::Value::String(value.to_string())),
+        Some(toml::Value::Array(
+            split_list(value)
+                .into_iter()
+                .map(toml::Value::String)
+                .collect(),
+        )),
+    ];
+    for candidate in candidates.into_iter().flatten() {
+        let mut updated = document.clone();
+        insert(&mut updated, segments.as_slice(), candidate)?;
+        let config = match updated.clone().try_into::<Config>() {
+            Ok(config) => config,
+            Err(_) => continue,
+        };
+        // unknown keys are ignored when parsing, so they are missing once serialized back
+        let known = toml::Value::try_from(&config)
+            .ok()
+            .map(|x| leaves(&x).iter().any(|(k, _)| k.as_str() == key))
+            .unwrap_or(false);
+        if !known {
+            return Err(TowError::config(&format!("unknown config key '{}'", key)));
+        }
+        return write_document(path, &updated);
+    }
+    Err(TowError::config(&format!(
+        "invalid value '{}' for config key '{}'",
+        value, key
+    )))
+}
+
+/// Removes key (e.g. a whole `profiles.work` table) from the config file at path.
+pub fn unset(path: &Path, key: &str) -> Result<(), TowError> {
+    let segments = split_key(key)?;
+    let content = read_to_string(path).map_err(|e| TowError::io(path, e))?;
+    let mut document = parse_document(content.as_str())?;
+    if !remove(&mut document, segments.as_slice()) {
+        return Err(TowError::not_found(&format!(
+            "config key '{}' is not set in {}",
+            join_key(segments.as_slice()),
+            path.display()
+        )));
+    }
+    write_document(path, &document)
+}
+
+fn write_document(path: &Path, document: &toml::Value) -> Result<(), TowError> {
+    let content = toml::to_string_pretty(document)
+        .map_err(|e| TowError::config_with("cannot serialize config", e))?;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| TowError::io(parent, e))?;
+    }
+    write(path, content).map_err(|e| TowError::io(path, e))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    match env::var(TOW_CONFIG_ENV) {
+        Ok(x) => Some(PathBuf::from(x)),
+        Err(_) => {
+            dirs::config_dir().map(|x| x.join(TOW_CONFIG_FOLDER_NAME).join(TOW_CONFIG_FILENAME))
+        }
+    }
+}
+
+pub fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn parse_document(content: &str) -> Result<toml::Value, TowError> {
+    toml::from_str(content).map_err(|e| TowError::config_with("cannot parse config file", e))
+}
+
+/// Dotted keys of the values in document, arrays are values too.
+fn leaves(document: &toml::Value) -> Vec<(String, toml::Value)> {
+    fn walk(prefix: &mut Vec<String>, value: &toml::Value, out: &mut Vec<(String, toml::Value)>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (name, value) in table {
+                    prefix.push(name.to_owned());
+                    walk(prefix, value, out);
+                    prefix.pop();
+                }
+            }
+            value => out.push((join_key(prefix.as_slice()), value.to_owned())),
+        }
+    }
+    let mut out = Vec::new();
+    walk(&mut Vec::new(), document, &mut out);
+    out
+}
+
+fn is_secret(key: &str) -> bool {
+    key.starts_with("tokens.")
+        || SECRET_KEYS
+            .iter()
+            .any(|x| key.rsplit('.').next() == Some(*x))
+}
+
+/// Splits `credentials."github.com".token` into its segments.
+fn split_key(key: &str) -> Result<Vec<String>, TowError> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in key.trim().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => segments.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    segments.push(current);
+    if quoted || segments.iter().any(|x| x.is_empty()) {
+        return Err(TowError::config(&format!("invalid config key '{}'", key)));
+    }
+    Ok(segments)
+}
+
+/// Quotes the segments that are not bare toml keys.
+fn join_key(segments: &[String]) -> String {
+    segments
+        .iter()
+        .map(|x| {
+            match x
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                true => x.to_owned(),
+                false => format!("\"{}\"", x),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn insert(
+    document: &mut toml::Value,
+    segments: &[String],
+    value: toml::Value,
+) -> Result<(), TowError> {
+    let (last, parents) = segments.split_last().unwrap_or_else(|| unreachable!());
+    let mut table = document
+        .as_table_mut()
+        .ok_or_else(|| TowError::config("the config file is not a table"))?;
+    for segment in parents {
+        table = table
+            .entry(segment.to_owned())
+            .or_insert_with(|| toml::Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| TowError::config(&format!("'{}' is not a table", segment)))?;
+    }
+    table.insert(last.to_owned(), value);
+    Ok(())
+}
+
+fn default_store_dir() -> Result<PathBuf, TowError> {
+    Ok(default_data_dir()?.join(TOW_DATA_FOLDER_NAME))
+}
+
+fn default_profile_store_dir(profile: &str) -> Result<PathBuf, TowError> {
+    Ok(default_store_dir()?
+        .join(TOW_PROFILES_FOLDER_NAME)
+        .join(profile))
+}
+
+/// Removes the value at segments and the tables left empty by that, false if it is not set.
+fn remove(document: &mut toml::Value, segments: &[String]) -> bool {
+    let table = match document.as_table_mut() {
+        Some(table) => table,
+        None => return false,
+    };
+    let (first, rest) = match segments.split_first() {
+        Some(x) => x,
+        None => return false,
+    };
+    if rest.is_empty() {
+        return table.remove(first).is_some();
+    }
+    let removed = table
+        .get_mut(first)
+        .map(|x| remove(x, rest))
+        .unwrap_or(false);
+    if removed && table[first].as_table().map(|x| x.is_empty()) == Some(true) {
+        table.remove(first);
+    }
+    removed
+}
+
+#[cfg(target_os = "macos")]
+fn default_bin_dir() -> Result<PathBuf, TowError> {
+    dirs::home_dir()
+        .map(|x| x.join(".local").join("bin"))
+        .ok_or_else(|| no_default_dir("dirs.binaries", TOW_BINARIES_DIR_ENV))
+}
+
+#[cfg(target_os = "macos")]
+fn default_data_dir() -> Result<PathBuf, TowError> {
+    dirs::home_dir()
+        .map(|x| x.join(".local").join("share"))
+        .ok_or_else(|| no_default_dir("dirs.store", TOW_STORE_DIR_ENV))
+}
+
+#[cfg(target_os = "linux")]
+fn default_bin_dir() -> Result<PathBuf, TowError> {
+    dirs::executable_dir().ok_or_else(|| no_default_dir("dirs.binaries", TOW_BINARIES_DIR_ENV))
+}
+
+#[cfg(target_os = "linux")]
+fn default_data_dir() -> Result<PathBuf, TowError> {
+    dirs::data_dir().ok_or_else(|| no_default_dir("dirs.store", TOW_STORE_DIR_ENV))
+}
+
+#[cfg(target_os = "windows")]
+fn default_bin_dir() -> Result<PathBuf, TowError> {
+    Err(no_default_dir("dirs.binaries", TOW_BINARIES_DIR_ENV))
+}
+
+#[cfg(target_os = "windows")]
+fn default_data_dir() -> Result<PathBuf, TowError> {
+    Err(no_default_dir("dirs.store", TOW_STORE_DIR_ENV))
+}
+
+fn no_default_dir(key: &str, env: &str) -> TowError {
+    TowError::config(&format!(
+        "cannot find a default dir on this platform, set {} or `{}` in the config file",
+        env, key
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_credentials() {
+        let config = Config::parse(
+            r#"
+            [credentials."github.com"]
+            token = "gh-token"
+
+            [credentials."artifacts.corp"]
+            username = "user"
+            password = "hunter2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.credentials.len(), 2);
+        assert_eq!(
+            config.credentials["github.com"].token.as_deref(),
+            Some("gh-token")
+        );
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("gh-token"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_parse_network() {
+        let config = Config::parse(
+            r#"
+            [network]
+            https_proxy = "http://proxy.corp:3128"
+            no_proxy = "localhost,.corp"
+            ca_cert = "/etc/ssl/corp.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.network.https_proxy.as_deref(),
+            Some("http://proxy.corp:3128")
+        );
+        assert_eq!(
+            config.network.ca_cert,
+            Some(PathBuf::from("/etc/ssl/corp.pem"))
+        );
+        assert!(!config.network.insecure);
+        assert_eq!(config.network.concurrency, 4);
+    }
+
+    #[test]
+    fn test_parse_mirrors() {
+        let config = Config::parse(
+            r#"
+            [[mirrors]]
+            prefix = "https://github.com/"
+            urls = ["https://artifacts.corp/github-proxy/", "https://backup.corp/github/"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mirrors.len(), 1);
+        assert_eq!(config.mirrors[0].urls.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_store_backend() {
+        assert_eq!(Config::parse("").unwrap().store.backend, StoreBackend::Json);
+        let config = Config::parse(
+            r#"
+            [store]
+            backend = "sqlite"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.store.backend, StoreBackend::Sqlite);
+        assert!(Config::parse("store = { backend = \"xml\" }").is_err());
+        assert_eq!(
+            "SQLite".parse::<StoreBackend>().unwrap(),
+            StoreBackend::Sqlite
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_and_invalid() {
+        assert!(Config::parse("").unwrap().credentials.is_empty());
+        assert!(Config::parse("credentials = 1").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        set(&path, "dirs.binaries", "/opt/bin").unwrap();
+        set(&path, "network.concurrency", "8").unwrap();
+        set(&path, "assets.prefer", "musl, x86_64").unwrap();
+        set(&path, "credentials.\"github.com\".token", "gh-token").unwrap();
+        assert!(set(&path, "network.concurrency", "many").is_err());
+        assert!(set(&path, "network.colour", "blue").is_err());
+
+        let mut config = Config::load_file(path.as_path()).unwrap();
+        config.set_by_flag("offline", "--offline");
+        assert_eq!(config.network.concurrency, 8);
+        assert_eq!(config.assets.prefer, vec!["musl", "x86_64"]);
+
+        let binaries = config.get("dirs.binaries").unwrap();
+        assert_eq!(binaries.raw_value(), "/opt/bin");
+        assert_eq!(binaries.origin, Origin::File(path.to_owned()));
+        assert_eq!(
+            config.get("offline").unwrap().origin.to_string(),
+            "flag --offline"
+        );
+        assert_eq!(
+            config.get("cache.max_size").unwrap().origin,
+            Origin::Default
+        );
+        let token = config.get("credentials.\"github.com\".token").unwrap();
+        assert_eq!(token.raw_value(), "<redacted>");
+        assert!(config.get("default_registry").is_err());
+        assert!(config
+            .settings()
+            .unwrap()
+            .iter()
+            .all(|x| !x.to_string().contains("gh-token")));
+    }
+}
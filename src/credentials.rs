@@ -0,0 +1,278 @@
+use log::debug;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::RequestBuilder;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use url::Url;
+
+use crate::config::{Config, HostCredentials};
+
+const NETRC_ENV: &str = "NETRC";
+const GITHUB_HOSTS: [&str; 3] = ["github.com", "api.github.com", "raw.githubusercontent.com"];
+const GITLAB_HOSTS: [&str; 1] = ["gitlab.com"];
+
+#[derive(Clone, PartialEq)]
+pub enum Credential {
+    Token(String),
+    Basic { username: String, password: String },
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Token(_) => write!(f, "Token(<redacted>)"),
+            Credential::Basic { username, .. } => write!(f, "Basic({}, <redacted>)", username),
+        }
+    }
+}
+
+/// Per-host credentials resolved from the tokens, the credentials of the config and ~/.netrc
+/// (in that order).
+#[derive(Debug, Default, Clone)]
+pub struct Credentials {
+    hosts: HashMap<String, Credential>,
+}
+
+impl Credentials {
+    pub fn load(config: &Config) -> Self {
+        let mut credentials = Self::default();
+        if let Some(netrc) = netrc_path().and_then(|x| read_to_string(x).ok()) {
+            credentials.extend(parse_netrc(netrc.as_str()));
+        }
+        credentials.extend(from_config(config));
+        credentials.extend(from_tokens(config));
+        credentials
+    }
+
+    pub fn get(&self, host: &str) -> Option<&Credential> {
+        self.hosts.get(host)
+    }
+
+    /// Attaches the auth header matching the url's host, if we have credentials for it.
+    /// Tokens go in Authorization (GitLab accepts them as Bearer too), the one header reqwest
+    /// drops when a redirect leaves the host, e.g. for release downloads on object storage.
+    pub fn authorize(&self, url: &Url, request: RequestBuilder) -> RequestBuilder {
+        let host = match url.host_str() {
+            None => return request,
+            Some(host) => host,
+        };
+        match self.get(host) {
+            None => request,
+            Some(Credential::Basic { username, password }) => {
+                debug!("using basic auth for {}", host);
+                request.basic_auth(username, Some(password))
+            }
+            Some(Credential::Token(token)) => {
+                debug!("using token auth for {}", host);
+                match HeaderValue::from_str(format!("Bearer {}", token).as_str()) {
+                    Err(_) => request,
+                    Ok(mut value) => {
+                        value.set_sensitive(true);
+                        request.header(AUTHORIZATION, value)
+                    }
+                }
+            }
+        }
+    }
+
+    fn extend(&mut self, other: HashMap<String, Credential>) {
+        self.hosts.extend(other)
+    }
+}
+
+/// `[tokens]` of the config, GITHUB_TOKEN and GITLAB_TOKEN.
+fn from_tokens(config: &Config) -> HashMap<String, Credential> {
+    let mut hosts = HashMap::new();
+    if let Some(token) = config.tokens.github.as_ref() {
+        for host in GITHUB_HOSTS {
+            hosts.insert(host.to_string(), Credential::Token(token.to_owned()));
+        }
+    }
+    if let Some(token) = config.tokens.gitlab.as_ref() {
+        for host in GITLAB_HOSTS {
+            hosts.insert(host.to_string(), Credential::Token(token.to_owned()));
+        }
+    }
+    hosts
+}
+
+fn from_config(config: &Config) -> HashMap<String, Credential> {
+    config
+        .credentials
+        .iter()
+        .filter_map(|(host, hc)| from_host_credentials(hc).map(|x| (host.to_owned(), x)))
+        .collect()
+}
+
+fn from_host_credentials(hc: &HostCredentials) -> Option<Credential> {
+    match (&hc.token, &hc.username, &hc.password) {
+        (Some(token), _, _) => Some(Credential::Token(token.to_owned())),
+        (None, Some(username), password) => Some(Credential::Basic {
+            username: username.to_owned(),
+            password: password.to_owned().unwrap_or_default(),
+        }),
+        _ => None,
+    }
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    match env::var(NETRC_ENV) {
+        Ok(x) => Some(PathBuf::from(x)),
+        Err(_) => dirs::home_dir().map(|x| x.join(".netrc")),
+    }
+}
+
+/// Parses `machine <host> login <user> password <pass>` entries, `default` is ignored.
+fn parse_netrc(content: &str) -> HashMap<String, Credential> {
+    let mut hosts = HashMap::new();
+    let mut machine: Option<String> = None;
+    let mut login: Option<String> = None;
+    let mut password: Option<String> = None;
+    let mut tokens = content.split_whitespace();
+
+    let mut flush = |machine: &mut Option<String>,
+                     login: &mut Option<String>,
+                     password: &mut Option<String>| {
+        if let (Some(m), Some(l)) = (machine.take(), login.take()) {
+            hosts.insert(
+                m,
+                Credential::Basic {
+                    username: l,
+                    password: password.take().unwrap_or_default(),
+                },
+            );
+        }
+        *password = None;
+    };
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                flush(&mut machine, &mut login, &mut password);
+                machine = tokens.next().map(|x| x.to_string());
+            }
+            "default" => {
+                flush(&mut machine, &mut login, &mut password);
+            }
+            "login" => login = tokens.next().map(|x| x.to_string()),
+            "password" => password = tokens.next().map(|x| x.to_string()),
+            _ => {}
+        }
+    }
+    flush(&mut machine, &mut login, &mut password);
+    hosts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_netrc() {
+        let hosts = parse_netrc(
+            "machine example.com login user password pass\n\
+             machine other.com\n  login other\n  password secret\n\
+             default login anonymous password guest",
+        );
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(
+            hosts["other.com"],
+            Credential::Basic {
+                username: "other".to_string(),
+                password: "secret".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_takes_precedence_over_netrc() {
+        let config = Config::parse(
+            r#"
+            [credentials."example.com"]
+            token = "from-config"
+            "#,
+        )
+        .unwrap();
+        let mut credentials = Credentials::default();
+        credentials.extend(parse_netrc("machine example.com login user password pass"));
+        credentials.extend(from_config(&config));
+        assert_eq!(
+            credentials.get("example.com"),
+            Some(&Credential::Token("from-config".to_string()))
+        );
+        assert!(!format!("{:?}", credentials).contains("from-config"));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let mut credentials = Credentials::default();
+        credentials.extend(HashMap::from([
+            (
+                "example.com".to_string(),
+                Credential::Token("abc".to_string()),
+            ),
+            (
+                "gitlab.example.com".to_string(),
+                Credential::Token("def".to_string()),
+            ),
+        ]));
+        let client = reqwest::Client::new();
+
+        let url = Url::parse("https://example.com/file").unwrap();
+        let request = credentials
+            .authorize(&url, client.get(url.as_str()))
+            .build()
+            .unwrap();
+        let header = request.headers().get(AUTHORIZATION).unwrap();
+        assert_eq!(header, "Bearer abc");
+        assert!(header.is_sensitive());
+
+        let url = Url::parse("https://gitlab.example.com/file").unwrap();
+        let request = credentials
+            .authorize(&url, client.get(url.as_str()))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers().get(AUTHORIZATION).unwrap(), "Bearer def");
+
+        let url = Url::parse("https://unknown.com/file").unwrap();
+        let request = credentials
+            .authorize(&url, client.get(url.as_str()))
+            .build()
+            .unwrap();
+        assert!(request.headers().is_empty());
+    }
+
+    #[test]
+    fn test_token_not_sent_across_redirects() {
+        let server = Url::parse(mockito::server_url().as_str()).unwrap();
+        let mut credentials = Credentials::default();
+        credentials.extend(HashMap::from([(
+            server.host_str().unwrap().to_string(),
+            Credential::Token("def".to_string()),
+        )]));
+        // same server under another host name, like a release redirected to object storage
+        let storage_url = format!("http://localhost:{}/storage/tool", server.port().unwrap());
+        let _release = mockito::mock("GET", "/release/tool")
+            .match_header("authorization", "Bearer def")
+            .with_status(302)
+            .with_header("location", storage_url.as_str())
+            .create();
+        let _storage = mockito::mock("GET", "/storage/tool")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_body("tool")
+            .create();
+
+        let url = server.join("/release/tool").unwrap();
+        let client = reqwest::Client::new();
+        let response =
+            tokio_test::block_on(credentials.authorize(&url, client.get(url.as_str())).send())
+                .unwrap();
+        assert_eq!(tokio_test::block_on(response.text()).unwrap(), "tool");
+        _release.assert();
+        _storage.assert();
+    }
+}
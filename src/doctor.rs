@@ -0,0 +1,291 @@
+use serde::Serialize;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{remove_file, File};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::app;
+use crate::config::StoreConfig;
+use crate::store::BinaryEntry;
+use crate::verify;
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Check {
+    fn ok(message: String) -> Self {
+        Self {
+            severity: Severity::Ok,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("[{}] {}", self.severity, self.message))
+    }
+}
+
+/// Checks the environment tow runs in, changing nothing besides what loading the store does.
+pub fn run(binaries_dir: &Path, store_dir: &Path, store_config: &StoreConfig) -> Vec<Check> {
+    let path_var = env::var_os("PATH");
+    let mut checks = vec![
+        check_on_path(binaries_dir, path_var.as_ref()),
+        check_writable(binaries_dir),
+    ];
+    match app::load_store(binaries_dir, store_dir, store_config, &|_| false) {
+        Err(e) => checks.push(Check::error(format!(
+            "cannot load the store in {}: {}",
+            store_dir.display(),
+            e
+        ))),
+        Ok(store) => {
+            let entries = store.list_binaries();
+            checks.push(Check::ok(format!(
+                "the store in {} loads, {} binaries installed",
+                store_dir.display(),
+                entries.len()
+            )));
+            for be in entries.iter() {
+                checks.extend(check_entry(be));
+                checks.extend(check_shadowed(
+                    be,
+                    entries.as_slice(),
+                    binaries_dir,
+                    path_var.as_ref(),
+                ));
+            }
+        }
+    }
+    checks
+}
+
+fn check_on_path(binaries_dir: &Path, path_var: Option<&OsString>) -> Check {
+    let on_path = path_var
+        .map(|x| env::split_paths(x).any(|x| same_dir(x.as_path(), binaries_dir)))
+        .unwrap_or(false);
+    match on_path {
+        true => Check::ok(format!("{} is on PATH", binaries_dir.display())),
+        false => Check::warning(format!(
+            "{} is not on PATH, installed binaries cannot be run by name",
+            binaries_dir.display()
+        )),
+    }
+}
+
+fn check_writable(binaries_dir: &Path) -> Check {
+    if !binaries_dir.is_dir() {
+        return Check::warning(format!(
+            "{} does not exist yet, it is created on the first install",
+            binaries_dir.display()
+        ));
+    }
+    let probe = binaries_dir.join(format!(".tow-doctor-{}", process::id()));
+    match File::create(&probe) {
+        Ok(_) => {
+            let _ = remove_file(&probe);
+            Check::ok(format!("{} is writable", binaries_dir.display()))
+        }
+        Err(e) => Check::error(format!("{} is not writable: {}", binaries_dir.display(), e)),
+    }
+}
+
+/// The files of the entry must exist, digests are left to `tow verify`.
+fn check_entry(be: &BinaryEntry) -> Vec<Check> {
+    let mut files = vec![be.path.to_owned()];
+    files.extend(be.stored_path.to_owned());
+    files
+        .into_iter()
+        .filter(|x| !x.exists())
+        .map(|x| {
+            Check::error(format!(
+                "{} {} points at {}, which does not exist",
+                be.name,
+                be.version,
+                x.display()
+            ))
+        })
+        .collect()
+}
+
+/// Files in binaries_dir that replaced the link to the entry, or earlier on PATH with its name.
+fn check_shadowed(
+    be: &BinaryEntry,
+    entries: &[&BinaryEntry],
+    binaries_dir: &Path,
+    path_var: Option<&OsString>,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+    // the link may point to another version of the binary
+    if be.stored_path.is_some() && be.path.exists() && !verify::is_tracked(be, entries) {
+        checks.push(Check::warning(format!(
+            "{} is not tracked by tow and shadows {} {}",
+            be.path.display(),
+            be.name,
+            be.version
+        )));
+    }
+
+    let command = match be.path.file_name() {
+        Some(x) => x,
+        None => return checks,
+    };
+    let earlier_dirs: Vec<PathBuf> = path_var
+        .map(|x| {
+            env::split_paths(x)
+                .take_while(|x| !same_dir(x.as_path(), binaries_dir))
+                .collect()
+        })
+        .unwrap_or_default();
+    for dir in earlier_dirs {
+        let shadowing = dir.join(command);
+        if shadowing.is_file() {
+            checks.push(Check::warning(format!(
+                "{} comes earlier on PATH and shadows {} {}",
+                shadowing.display(),
+                be.name,
+                be.version
+            )));
+        }
+    }
+    checks
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn test_check_on_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let bin = temp_dir.path().join("bin");
+        let other = temp_dir.path().join("other");
+        let path_var = env::join_paths([other.as_path(), bin.as_path()]).unwrap();
+        assert_eq!(
+            check_on_path(bin.as_path(), Some(&path_var)).severity,
+            Severity::Ok
+        );
+        assert_eq!(
+            check_on_path(temp_dir.path(), Some(&path_var)).severity,
+            Severity::Warning
+        );
+        assert_eq!(
+            check_on_path(bin.as_path(), None).severity,
+            Severity::Warning
+        );
+    }
+
+    #[test]
+    fn test_check_shadowed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let bin = temp_dir.path().join("bin");
+        let earlier = temp_dir.path().join("earlier");
+        create_dir_all(&bin).unwrap();
+        create_dir_all(&earlier).unwrap();
+        let path_var = env::join_paths([earlier.as_path(), bin.as_path()]).unwrap();
+        let be = BinaryEntry {
+            name: "tool".to_string(),
+            version: "v1".to_string(),
+            path: bin.join("tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: Some(temp_dir.path().join("stored").join("tool")),
+            sha256: None,
+            asset: None,
+        };
+        assert!(check_shadowed(&be, &[], bin.as_path(), Some(&path_var)).is_empty());
+        assert_eq!(check_entry(&be).len(), 2);
+
+        write(bin.join("tool"), "untracked").unwrap();
+        write(earlier.join("tool"), "earlier").unwrap();
+        let checks = check_shadowed(&be, &[], bin.as_path(), Some(&path_var));
+        assert_eq!(checks.len(), 2);
+        assert!(checks[0].message.contains("is not tracked by tow"));
+        assert!(checks[1].message.contains("comes earlier on PATH"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_shadowed_versions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stored = |version: &str| {
+            let path = temp_dir.path().join(version).join("tool");
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(&path, version).unwrap();
+            path
+        };
+        let v1 = BinaryEntry {
+            name: "tool".to_string(),
+            version: "v1".to_string(),
+            path: temp_dir.path().join("tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: Some(stored("v1")),
+            sha256: None,
+            asset: None,
+        };
+        let v2 = BinaryEntry {
+            version: "v2".to_string(),
+            stored_path: Some(stored("v2")),
+            ..v1.clone()
+        };
+        // the link belongs to v2, which does not make it untracked for v1
+        std::os::unix::fs::symlink(v2.stored_path.as_ref().unwrap(), &v1.path).unwrap();
+        assert!(check_shadowed(&v1, &[&v1, &v2], temp_dir.path(), None).is_empty());
+        assert_eq!(check_shadowed(&v1, &[&v1], temp_dir.path(), None).len(), 1);
+    }
+
+    #[test]
+    fn test_run_reports_broken_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write(temp_dir.path().join("towstore.json"), "{ broken").unwrap();
+        let checks = run(temp_dir.path(), temp_dir.path(), &StoreConfig::default());
+        assert!(checks
+            .iter()
+            .any(|x| x.severity == Severity::Error && x.message.contains("cannot load the store")));
+    }
+}
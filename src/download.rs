@@ -1,91 +1,258 @@
 use crate::errors::TowError;
+use crate::http::HttpClient;
+use crate::s3;
 use futures_util::StreamExt;
 use log::warn;
-use reqwest::header;
+use percent_encoding::percent_decode_str;
+use reqwest::{header, Method, Response};
 use std::cmp::min;
-use std::fs::File;
+use std::fs::{remove_file, rename, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use url::Url;
 
-pub async fn download_file(url: &Url, path: &Path) -> Result<PathBuf, TowError> {
+/// Suffix of files that are still being downloaded.
+pub const PART_SUFFIX: &str = ".part";
+
+#[derive(Debug)]
+pub struct Download {
+    pub path: PathBuf,
+    pub etag: Option<String>,
+}
+
+pub async fn download_file(
+    http: &HttpClient,
+    url: &Url,
+    path: &Path,
+) -> Result<Download, TowError> {
     if !path.is_dir() {
-        return Err(TowError::new(&format!(
+        return Err(TowError::config(&format!(
             "'{}' is not a directory",
             path.display()
         )));
     };
-    // disable auto-decompression so that content-length has some meaning
-    let client = reqwest::Client::builder()
-        .no_deflate()
-        .no_gzip()
-        .no_brotli()
-        .build()?;
     let url_str = url.as_str();
-    let res = client.get(url_str).send().await?;
+    let res = send(http, Method::GET, url).await?;
     let content_length = get_content_length(&res).unwrap_or_else(|| {
         warn!("cannot extract content-length");
         0
     });
-    let filename = get_filename(res.headers())?;
-    let full_path = path.join(filename);
+    let etag = get_etag(res.headers());
+    let filename = get_filename(res.headers(), res.url())?;
+    let full_path = path.join(&filename);
+    // written under another name, so an interrupted download is never taken for a complete one
+    let part_path = path.join(format!("{}{}", filename, PART_SUFFIX));
 
     let pb = progress::setup_progress_bar(url_str, content_length);
 
     // download chunks
-    let mut file = File::create(&full_path)?;
+    let mut file = File::create(&part_path).map_err(|e| TowError::io(&part_path, e))?;
     let mut downloaded: u64 = 0;
     let mut stream = res.bytes_stream();
 
     while let Some(item) = stream.next().await {
-        let chunk = item.map_err(|_| "Error while downloading file".to_owned())?;
-        file.write_all(&chunk)?;
-        let new = min(downloaded + (chunk.len() as u64), content_length);
+        let written = match item {
+            Ok(chunk) => file
+                .write_all(&chunk)
+                .map(|_| chunk.len())
+                .map_err(|e| TowError::io(&part_path, e)),
+            Err(e) => Err(e.into()),
+        };
+        let written = match written {
+            Ok(x) => x,
+            Err(e) => {
+                let _ = remove_file(&part_path);
+                return Err(e);
+            }
+        };
+        let new = min(downloaded + (written as u64), content_length);
         downloaded = new;
         pb.set_position(new);
     }
+    drop(file);
+    rename(&part_path, &full_path).map_err(|e| TowError::io(&full_path, e))?;
 
     pb.finish_with_message(format!("Downloaded {} to {}", url_str, full_path.display()));
-    Ok(full_path)
+    Ok(Download {
+        path: full_path,
+        etag,
+    })
+}
+
+/// Asks the server for the current ETag of the url without downloading the body.
+pub async fn fetch_etag(http: &HttpClient, url: &Url) -> Result<Option<String>, TowError> {
+    let res = send(http, Method::HEAD, url).await?;
+    Ok(get_etag(res.headers()))
+}
+
+async fn send(http: &HttpClient, method: Method, url: &Url) -> Result<Response, TowError> {
+    match url.scheme() {
+        s3::SCHEME => Ok(s3::signed_request(http, method, url)?
+            .send()
+            .await?
+            .error_for_status()?),
+        _ => http.send(method, url).await,
+    }
+}
+
+fn get_etag(headers: &header::HeaderMap) -> Option<String> {
+    headers
+        .get(header::ETAG)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string())
 }
 
 fn get_content_length(response: &reqwest::Response) -> Option<u64> {
     response.content_length()
 }
 
-fn get_filename(headers: &header::HeaderMap) -> Result<&str, TowError> {
-    let header = match headers.get(header::CONTENT_DISPOSITION) {
-        None => Err(TowError::new(&format!(
-            "no {} header",
-            header::CONTENT_DISPOSITION
-        ))),
-        Some(cd) => Ok(cd.to_str()?),
-    }?;
-    parse_filename_from_content_disposition(header)
+/// Derives the filename from the content-disposition header and falls back to the last
+/// path segment of the final (post-redirect) url, also when the header is not visible ascii.
+/// The result is always safe to join to a dir.
+fn get_filename(headers: &header::HeaderMap, final_url: &Url) -> Result<String, TowError> {
+    let from_header = match headers.get(header::CONTENT_DISPOSITION) {
+        None => None,
+        Some(cd) => match cd.to_str() {
+            Ok(cd) => parse_filename_from_content_disposition(cd)
+                .map_err(|e| warn!("{}", e))
+                .ok(),
+            Err(_) => {
+                warn!(
+                    "ignoring {} header that is not visible ascii",
+                    header::CONTENT_DISPOSITION
+                );
+                None
+            }
+        },
+    };
+    from_header
+        .and_then(|x| sanitize_filename(&x))
+        .or_else(|| get_filename_from_url(final_url))
+        .ok_or_else(|| {
+            TowError::config(&format!(
+                "cannot derive a filename from {} header or url '{}'",
+                header::CONTENT_DISPOSITION,
+                final_url
+            ))
+        })
+}
+
+fn get_filename_from_url(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let decoded = percent_decode_str(segment).decode_utf8().ok()?;
+    sanitize_filename(&decoded)
 }
 
-fn parse_filename_from_content_disposition(header: &str) -> Result<&str, TowError> {
-    match header.split_once("filename=") {
-        None => Err(TowError::new(&format!(
-            "cannot get filename from '{}'",
-            header
-        ))),
-        Some(tup) => {
-            let mut extracted = tup.1.split_once(';').map(|x| x.0).unwrap_or(tup.1);
-            if extracted.starts_with('\"') {
-                extracted = &extracted[1..];
+/// Parses the filename according to RFC 6266, preferring the RFC 5987 encoded `filename*=`.
+fn parse_filename_from_content_disposition(header: &str) -> Result<String, TowError> {
+    let mut filename = None;
+    let mut filename_ext = None;
+    for param in split_params(header) {
+        let (key, value) = match param.split_once('=') {
+            None => continue,
+            Some(tup) => tup,
+        };
+        // be lenient about separators before the parameter name, e.g. "attachment: filename=x"
+        let key = key
+            .rsplit(|c: char| c.is_whitespace() || c == ':')
+            .next()
+            .unwrap_or(key)
+            .to_ascii_lowercase();
+        match key.as_str() {
+            "filename" => filename = Some(unquote(value.trim())),
+            "filename*" => filename_ext = decode_ext_value(value.trim()),
+            _ => {}
+        }
+    }
+    filename_ext
+        .or(filename)
+        .ok_or_else(|| TowError::config(&format!("cannot get filename from '{}'", header)))
+}
+
+/// Splits header parameters on ';' ignoring the ones inside quoted strings.
+fn split_params(header: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                params.push(&header[start..i]);
+                start = i + 1;
             }
-            if extracted.ends_with('\"') {
-                extracted = &extracted[0..extracted.len() - 1]
+            _ => {}
+        }
+    }
+    params.push(&header[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"') {
+        None => value.to_string(),
+        Some(quoted) => {
+            let quoted = quoted.strip_suffix('"').unwrap_or(quoted);
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    _ => unquoted.push(c),
+                }
             }
-            Ok(extracted)
+            unquoted
+        }
+    }
+}
+
+/// Decodes RFC 5987 ext-value: charset'language'percent-encoded-value.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_ascii_lowercase();
+    let _language = parts.next()?;
+    let bytes: Vec<u8> = percent_decode_str(parts.next()?).collect();
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        // latin1 maps directly to the first 256 unicode code points
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => {
+            warn!("unsupported charset '{}' in filename*", charset);
+            None
         }
     }
 }
+
+/// Keeps only the last path component so names like `../../.bashrc` cannot escape the target dir.
+fn sanitize_filename(name: &str) -> Option<String> {
+    let last = name.rsplit(['/', '\\']).next()?;
+    let sanitized: String = last
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .trim_start_matches('.')
+        .to_string();
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
 mod progress {
     use indicatif::{ProgressBar, ProgressStyle};
 
+    use crate::logs;
+
+    /// Hidden when stderr is not a terminal or with `-q`.
     pub fn setup_progress_bar(url: &str, total_size: u64) -> ProgressBar {
+        if !logs::show_progress() {
+            return ProgressBar::hidden();
+        }
         let pb = ProgressBar::new(total_size);
         pb.set_style(ProgressStyle::default_bar()
         .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
@@ -114,7 +281,7 @@ mod test {
             },
             TestData {
                 header: r#"attachment; filename="EURO rates"; filename*=utf-8''%e2%82%ac%20rates"#,
-                expected: "EURO rates",
+                expected: "€ rates",
             },
             TestData {
                 header: "attachment; filename=omáèka.jpg",
@@ -124,6 +291,14 @@ mod test {
                 header: "attachment; filename=EXAMPLE- I'm ößä.dat; filename*=iso-8859-1''EXAMPLE-%20I%27m%20%F6%DF%E4.dat",
                 expected: "EXAMPLE- I'm ößä.dat",
             },
+            TestData {
+                header: r#"attachment; filename="a;b \"c\".txt""#,
+                expected: r#"a;b "c".txt"#,
+            },
+            TestData {
+                header: "attachment; FILENAME*=UTF-8'en'%C3%A9t%C3%A9.bin",
+                expected: "été.bin",
+            },
         ];
 
         for td in inputs {
@@ -133,6 +308,121 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("../../.bashrc").unwrap(), "bashrc");
+        assert_eq!(sanitize_filename("..\\..\\tool.exe").unwrap(), "tool.exe");
+        assert_eq!(sanitize_filename("/etc/passwd").unwrap(), "passwd");
+        assert_eq!(sanitize_filename("tool\n").unwrap(), "tool");
+        assert!(sanitize_filename("..").is_none());
+        assert!(sanitize_filename("dir/").is_none());
+    }
+
+    #[test]
+    fn test_filename_fallbacks() {
+        let url = Url::parse("https://example.com/releases/download/v1/tool%20x?raw=1").unwrap();
+        let mut headers = header::HeaderMap::new();
+        assert_eq!(get_filename(&headers, &url).unwrap(), "tool x");
+
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=../../.bashrc".parse().unwrap(),
+        );
+        assert_eq!(get_filename(&headers, &url).unwrap(), "bashrc");
+
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            header::HeaderValue::from_bytes(b"attachment; filename=\"t\xc3\xb6ol\"").unwrap(),
+        );
+        assert_eq!(get_filename(&headers, &url).unwrap(), "tool x");
+
+        let url = Url::parse("https://example.com/").unwrap();
+        assert!(get_filename(&header::HeaderMap::new(), &url).is_err());
+    }
+
+    #[test]
+    fn test_download_file_without_content_disposition() {
+        let endpoint = "/download/tool-v1.0";
+        let _m = mock("GET", endpoint)
+            .with_status(200)
+            .with_body("binary")
+            .create();
+
+        let url = Url::parse(&format!("{}{}", &mockito::server_url(), endpoint)).unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let download =
+            tokio_test::block_on(download_file(&HttpClient::default(), &url, temp_dir.path()))
+                .unwrap();
+        assert_eq!(download.path, temp_dir.path().join("tool-v1.0"));
+    }
+
+    #[test]
+    fn test_download_file_with_credentials() {
+        let endpoint = "/private/tool";
+        let _m = mock("GET", endpoint)
+            .match_header("authorization", "Bearer secret")
+            .with_status(200)
+            .with_body("binary")
+            .create();
+
+        let config = crate::config::Config::parse(
+            r#"
+            [credentials."127.0.0.1"]
+            token = "secret"
+            "#,
+        )
+        .unwrap();
+        let http = HttpClient::new(
+            crate::credentials::Credentials::load(&config),
+            &config.network,
+        )
+        .unwrap();
+        let url = Url::parse(&format!("{}{}", &mockito::server_url(), endpoint)).unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        tokio_test::block_on(download_file(&http, &url, temp_dir.path())).unwrap();
+        _m.assert();
+    }
+
+    #[test]
+    fn test_download_file_from_s3() {
+        let _m = mock("GET", "/bucket/tools/tool/1.0/tool")
+            .match_header(
+                "authorization",
+                mockito::Matcher::Regex("^AWS4-HMAC-SHA256 Credential=minio/".to_string()),
+            )
+            .match_header("x-amz-content-sha256", mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("binary")
+            .create();
+
+        let http = HttpClient::default().with_s3(crate::config::S3Config {
+            endpoint: Some(mockito::server_url()),
+            access_key_id: Some("minio".to_string()),
+            secret_access_key: Some("minio123".to_string()),
+            ..Default::default()
+        });
+        let url = Url::parse("s3://bucket/tools/tool/1.0/tool").unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let download = tokio_test::block_on(download_file(&http, &url, temp_dir.path())).unwrap();
+        assert_eq!(download.path, temp_dir.path().join("tool"));
+        _m.assert();
+    }
+
+    #[test]
+    fn test_download_file_error_status() {
+        let _m = mock("GET", "/notfound").with_status(404).create();
+        let url = Url::parse(&format!("{}/notfound", &mockito::server_url())).unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let err =
+            tokio_test::block_on(download_file(&HttpClient::default(), &url, temp_dir.path()))
+                .unwrap_err();
+        assert!(err.to_string().contains("404"));
+    }
+
     #[test]
     fn test_download_file() {
         let filename = "hello.txt";
@@ -156,11 +446,18 @@ mod test {
         let temp_path = temp_dir.path();
 
         // 1 - success
-        tokio_test::block_on(download_file(&url, &temp_path)).unwrap();
+        let download =
+            tokio_test::block_on(download_file(&HttpClient::default(), &url, temp_path)).unwrap();
         assert!(temp_path.join(filename).is_file());
+        assert_eq!(download.path, temp_path.join(filename));
 
         // 2 - failure
-        let err = tokio_test::block_on(download_file(&url, &temp_path.join(filename))).unwrap_err();
+        let err = tokio_test::block_on(download_file(
+            &HttpClient::default(),
+            &url,
+            &temp_path.join(filename),
+        ))
+        .unwrap_err();
         assert!(err.to_string().contains("not a directory"));
     }
 }
@@ -1,60 +1,318 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 
+use reqwest::StatusCode;
+
+type Source = Box<dyn error::Error + Send + Sync>;
+
+/// Errors of tow, by what went wrong, so that the cli can exit with a code per category.
+/// Errors of other crates are kept as `source()`.
 #[derive(Debug)]
-pub struct TowError {
-    msg: String,
+pub enum TowError {
+    /// the server could not be reached: dns, connection, tls or timeout
+    Network(reqwest::Error),
+    /// the server answered with an error status
+    Http {
+        status: u16,
+        url: String,
+        source: Option<reqwest::Error>,
+    },
+    /// a file could not be read or written
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// the store cannot be read, locked, migrated or restored
+    Store {
+        msg: String,
+        source: Option<Source>,
+    },
+    /// installed binaries do not match their recorded digests, or `tow doctor` found errors
+    Verification(String),
+    /// a binary, release, backup or cache entry that does not exist
+    NotFound(String),
+    /// the change clashes with what is installed
+    Conflict(String),
+    /// invalid configuration, arguments or sources
+    Config {
+        msg: String,
+        source: Option<Source>,
+    },
+    Other {
+        msg: String,
+        source: Option<Source>,
+    },
 }
 
 impl TowError {
     pub fn new(msg: &str) -> TowError {
-        TowError {
+        TowError::Other {
             msg: msg.to_owned(),
+            source: None,
+        }
+    }
+
+    pub fn store(msg: &str) -> TowError {
+        TowError::Store {
+            msg: msg.to_owned(),
+            source: None,
+        }
+    }
+
+    /// Store error caused by source, whose message follows msg.
+    pub fn store_with<E>(msg: &str, source: E) -> TowError
+    where
+        E: error::Error + Send + Sync + 'static,
+    {
+        TowError::Store {
+            msg: format!("{}: {}", msg, source),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn verification(msg: &str) -> TowError {
+        TowError::Verification(msg.to_owned())
+    }
+
+    pub fn not_found(msg: &str) -> TowError {
+        TowError::NotFound(msg.to_owned())
+    }
+
+    pub fn conflict(msg: &str) -> TowError {
+        TowError::Conflict(msg.to_owned())
+    }
+
+    pub fn config(msg: &str) -> TowError {
+        TowError::Config {
+            msg: msg.to_owned(),
+            source: None,
+        }
+    }
+
+    /// Config error caused by source, whose message follows msg.
+    pub fn config_with<E>(msg: &str, source: E) -> TowError
+    where
+        E: error::Error + Send + Sync + 'static,
+    {
+        TowError::Config {
+            msg: format!("{}: {}", msg, source),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// Io error that names the file it happened on.
+    pub fn io(path: &Path, source: io::Error) -> TowError {
+        TowError::Io {
+            path: Some(path.to_owned()),
+            source,
+        }
+    }
+
+    /// Process exit code of the category, documented in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TowError::Other { .. } => 1,
+            TowError::Config { .. } => 3,
+            TowError::Network(_) => 4,
+            TowError::Http { .. } => 5,
+            TowError::NotFound(_) => 6,
+            TowError::Conflict(_) => 7,
+            TowError::Store { .. } => 8,
+            TowError::Verification(_) => 9,
+            TowError::Io { .. } => 10,
+        }
+    }
+
+    /// What the user can do about it, if the message itself does not say.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            TowError::Network(_) => {
+                Some("check the connection and proxy settings, or pass --offline to install from the download cache")
+            }
+            TowError::Store { .. } => Some("run `tow doctor` to check the store"),
+            TowError::Verification(_) => {
+                Some("reinstall the affected binaries, or fix what the failed checks report")
+            }
+            _ => None,
         }
     }
 }
 
 impl fmt::Display for TowError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match self {
+            TowError::Network(e) => write!(f, "network error: {}", e),
+            TowError::Http { status, url, .. } => {
+                let reason = StatusCode::from_u16(*status)
+                    .ok()
+                    .and_then(|x| x.canonical_reason())
+                    .unwrap_or_default();
+                write!(f, "{} answered HTTP {} {}", url, status, reason)
+            }
+            TowError::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "cannot access '{}': {}", path.display(), source),
+            TowError::Io { path: None, source } => write!(f, "{}", source),
+            TowError::Store { msg, .. }
+            | TowError::Config { msg, .. }
+            | TowError::Other { msg, .. } => write!(f, "{}", msg),
+            TowError::Verification(msg) | TowError::NotFound(msg) | TowError::Conflict(msg) => {
+                write!(f, "{}", msg)
+            }
+        }
     }
 }
 
-impl error::Error for TowError {}
+impl error::Error for TowError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TowError::Network(e) => Some(e),
+            TowError::Http {
+                source: Some(e), ..
+            } => Some(e),
+            TowError::Io { source, .. } => Some(source),
+            TowError::Store {
+                source: Some(e), ..
+            }
+            | TowError::Config {
+                source: Some(e), ..
+            }
+            | TowError::Other {
+                source: Some(e), ..
+            } => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<String> for TowError {
     fn from(e: String) -> Self {
-        TowError { msg: e }
+        TowError::new(&e)
     }
 }
 
 impl From<io::Error> for TowError {
     fn from(e: io::Error) -> Self {
-        TowError::new(&e.to_string())
+        TowError::Io {
+            path: None,
+            source: e,
+        }
     }
 }
 
 impl From<url::ParseError> for TowError {
     fn from(e: url::ParseError) -> Self {
-        TowError::new(&e.to_string())
+        TowError::config_with("invalid url", e)
     }
 }
 
 impl From<serde_json::Error> for TowError {
     fn from(e: serde_json::Error) -> Self {
-        TowError::new(&e.to_string())
+        TowError::Other {
+            msg: e.to_string(),
+            source: Some(Box::new(e)),
+        }
     }
 }
 
 impl From<reqwest::Error> for TowError {
     fn from(e: reqwest::Error) -> Self {
-        TowError::new(&e.to_string())
+        match (e.status(), e.url()) {
+            (Some(status), Some(url)) => TowError::Http {
+                status: status.as_u16(),
+                url: url.to_string(),
+                source: Some(e),
+            },
+            _ => TowError::Network(e),
+        }
     }
 }
 
 impl From<reqwest::header::ToStrError> for TowError {
     fn from(e: reqwest::header::ToStrError) -> Self {
-        TowError::new(&e.to_string())
+        TowError::Other {
+            msg: e.to_string(),
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for TowError {
+    fn from(e: rusqlite::Error) -> Self {
+        TowError::Store {
+            msg: e.to_string(),
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_categories() {
+        let e = TowError::io(
+            Path::new("/nowhere/tool"),
+            io::Error::new(io::ErrorKind::NotFound, "gone"),
+        );
+        assert_eq!(e.to_string(), "cannot access '/nowhere/tool': gone");
+        assert_eq!(e.source().unwrap().to_string(), "gone");
+        assert_eq!(e.exit_code(), 10);
+
+        let e: TowError = io::Error::other("disk full").into();
+        assert_eq!(e.to_string(), "disk full");
+
+        let e: TowError = serde_json::from_str::<u8>("{").unwrap_err().into();
+        assert!(e.source().is_some());
+        assert_eq!(e.exit_code(), 1);
+
+        assert_eq!(
+            TowError::not_found("tool-v1 is not in the store").exit_code(),
+            6
+        );
+        assert_eq!(TowError::conflict("clash").exit_code(), 7);
+        assert!(TowError::store("locked").hint().is_some());
+
+        // wrapped errors stay reachable as the source
+        let e = crate::config::Config::parse("[dirs").unwrap_err();
+        assert!(matches!(
+            e,
+            TowError::Config {
+                source: Some(_),
+                ..
+            }
+        ));
+        assert!(e.to_string().starts_with("cannot parse config file: "));
+        assert_eq!(e.exit_code(), 3);
+        let e = TowError::store_with("cannot migrate", TowError::config("bad binaries"));
+        assert_eq!(e.to_string(), "cannot migrate: bad binaries");
+        assert_eq!(e.source().unwrap().to_string(), "bad binaries");
+
+        // file errors name the file
+        let missing = Path::new("/nowhere/tool");
+        let e = crate::cache::sha256_file(missing).unwrap_err();
+        assert!(matches!(e, TowError::Io { path: Some(ref x), .. } if x == missing));
+    }
+
+    #[test]
+    fn test_http_status() {
+        let _m = mockito::mock("GET", "/missing").with_status(404).create();
+        let url = format!("{}/missing", mockito::server_url());
+        let e: TowError = tokio_test::block_on(reqwest::get(url.as_str()))
+            .unwrap()
+            .error_for_status()
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, TowError::Http { status: 404, .. }));
+        assert_eq!(
+            e.to_string(),
+            format!("{} answered HTTP 404 Not Found", url)
+        );
+        assert_eq!(e.exit_code(), 5);
     }
 }
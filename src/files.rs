@@ -0,0 +1,145 @@
+use std::fs::{copy, create_dir_all, remove_file, rename, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::TowError;
+
+/// Suffix of the temp files written next to their target before the rename.
+pub const TEMP_SUFFIX: &str = ".tow-tmp";
+
+/// Writes the file so that readers see either the old or the new content, never a partial one:
+/// temp file in the same dir, fsync, rename over the target and fsync the dir.
+pub fn write_atomically(path: &Path, content: &[u8]) -> Result<(), TowError> {
+    let dir = parent_dir(path)?;
+    create_dir_all(dir).map_err(|e| TowError::io(dir, e))?;
+    let temp_path = temp_sibling(path)?;
+    {
+        let mut file = File::create(&temp_path).map_err(|e| TowError::io(&temp_path, e))?;
+        file.write_all(content)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| TowError::io(&temp_path, e))?;
+    }
+    if let Err(e) = rename(&temp_path, path) {
+        let _ = remove_file(&temp_path);
+        return Err(TowError::io(path, e));
+    }
+    sync_dir(dir)
+}
+
+/// Renames the file, falling back to copy and remove when crossing filesystems.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), TowError> {
+    if rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let temp_path = temp_sibling(to)?;
+    copy(from, &temp_path).map_err(|e| TowError::io(from, e))?;
+    File::open(&temp_path)
+        .and_then(|x| x.sync_all())
+        .map_err(|e| TowError::io(&temp_path, e))?;
+    rename(&temp_path, to).map_err(|e| TowError::io(to, e))?;
+    remove_file(from).map_err(|e| TowError::io(from, e))?;
+    Ok(())
+}
+
+/// Points link at target, replacing whatever was at link in a single rename.
+#[cfg(unix)]
+pub fn link_atomically(target: &Path, link: &Path) -> Result<(), TowError> {
+    let dir = parent_dir(link)?;
+    create_dir_all(dir).map_err(|e| TowError::io(dir, e))?;
+    let temp_path = temp_sibling(link)?;
+    remove_if_exists(&temp_path)?;
+    std::os::unix::fs::symlink(target, &temp_path).map_err(|e| TowError::io(&temp_path, e))?;
+    rename(&temp_path, link).map_err(|e| TowError::io(link, e))?;
+    sync_dir(dir)
+}
+
+#[cfg(not(unix))]
+pub fn link_atomically(target: &Path, link: &Path) -> Result<(), TowError> {
+    let temp_path = temp_sibling(link)?;
+    copy(target, &temp_path).map_err(|e| TowError::io(target, e))?;
+    rename(&temp_path, link).map_err(|e| TowError::io(link, e))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn make_executable(path: &Path) -> Result<(), TowError> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = path
+        .metadata()
+        .map_err(|e| TowError::io(path, e))?
+        .permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    std::fs::set_permissions(path, permissions).map_err(|e| TowError::io(path, e))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn make_executable(_: &Path) -> Result<(), TowError> {
+    Ok(())
+}
+
+pub fn remove_if_exists(path: &Path) -> Result<(), TowError> {
+    match remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(TowError::io(path, e)),
+        _ => Ok(()),
+    }
+}
+
+fn temp_sibling(path: &Path) -> Result<PathBuf, TowError> {
+    let file_name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| TowError::config(&format!("'{}' has no file name", path.display())))?;
+    Ok(path.with_file_name(format!(".{}{}", file_name, TEMP_SUFFIX)))
+}
+
+fn parent_dir(path: &Path) -> Result<&Path, TowError> {
+    path.parent()
+        .ok_or_else(|| TowError::config(&format!("'{}' has no parent dir", path.display())))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), TowError> {
+    File::open(dir)
+        .and_then(|x| x.sync_all())
+        .map_err(|e| TowError::io(dir, e))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> Result<(), TowError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{read_dir, read_to_string};
+
+    #[test]
+    fn test_write_atomically() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("nested").join("file.json");
+
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "second");
+        // no temp files are left behind
+        assert_eq!(read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_link_atomically() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = temp_dir.path().join("first");
+        let second = temp_dir.path().join("second");
+        let link = temp_dir.path().join("bin").join("tool");
+        write_atomically(&first, b"1").unwrap();
+        write_atomically(&second, b"2").unwrap();
+
+        link_atomically(&first, &link).unwrap();
+        assert_eq!(read_to_string(&link).unwrap(), "1");
+        link_atomically(&second, &link).unwrap();
+        assert_eq!(read_to_string(&link).unwrap(), "2");
+    }
+}
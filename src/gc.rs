@@ -0,0 +1,277 @@
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::{read_dir, read_link, remove_dir, remove_dir_all, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::cache::{self, CacheEntry};
+use crate::download::PART_SUFFIX;
+use crate::errors::TowError;
+use crate::files::TEMP_SUFFIX;
+use crate::local_store::STORED_BINARIES_DIR;
+use crate::store::BinaryEntry;
+
+/// Cache entries not used for this long are collected by default.
+pub const DEFAULT_CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Downloads and temp files younger than this may belong to a tow process running right now.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GarbageKind {
+    /// version dir in the store that no entry refers to
+    VersionDir,
+    /// complete or partial download that never made it into the store
+    Download,
+    /// temp file of an interrupted atomic write
+    TempFile,
+    /// link in binaries_dir into the store, to a binary that is gone
+    DanglingLink,
+    /// cache blob that no cache entry refers to
+    CacheBlob,
+}
+
+impl Display for GarbageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GarbageKind::VersionDir => "unreferenced version dir",
+            GarbageKind::Download => "leftover download",
+            GarbageKind::TempFile => "temp file",
+            GarbageKind::DanglingLink => "dangling link",
+            GarbageKind::CacheBlob => "orphaned cache blob",
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Garbage {
+    pub kind: GarbageKind,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl Garbage {
+    pub fn new(kind: GarbageKind, path: PathBuf) -> Self {
+        let size = size_of(path.as_path());
+        Self { kind, path, size }
+    }
+}
+
+impl Display for Garbage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} {} ({})",
+            self.kind,
+            self.path.display(),
+            cache::format_size(self.size)
+        ))
+    }
+}
+
+/// What `tow gc` removed, or would remove on a dry run.
+#[derive(Default)]
+pub struct GcReport {
+    pub files: Vec<Garbage>,
+    pub cache_entries: Vec<CacheEntry>,
+}
+
+impl GcReport {
+    pub fn reclaimed(&self) -> u64 {
+        self.files.iter().map(|x| x.size).sum::<u64>()
+            + self.cache_entries.iter().map(|x| x.size).sum::<u64>()
+    }
+}
+
+/// Finds files of tow that nothing refers to, must be called under the store lock with
+/// the entries read under it. Downloads and temp files younger than grace are left alone.
+pub fn find(
+    store_dir: &Path,
+    binaries_dir: &Path,
+    downloads_dir: &Path,
+    entries: &[&BinaryEntry],
+    grace: Duration,
+) -> Result<Vec<Garbage>, TowError> {
+    let mut garbage = Vec::new();
+    let stored_binaries_dir = store_dir.join(STORED_BINARIES_DIR);
+
+    for name_dir in list_dir(stored_binaries_dir.as_path())? {
+        for version_dir in list_dir(name_dir.as_path())? {
+            let referenced = entries.iter().any(|x| {
+                x.stored_path
+                    .as_deref()
+                    .and_then(|x| x.parent())
+                    .map(|x| same_path(x, version_dir.as_path()))
+                    .unwrap_or(false)
+            });
+            if !referenced {
+                garbage.push(Garbage::new(GarbageKind::VersionDir, version_dir));
+            }
+        }
+    }
+
+    for download in list_dir(downloads_dir)? {
+        if is_older(download.as_path(), grace) {
+            garbage.push(Garbage::new(GarbageKind::Download, download));
+        }
+    }
+
+    for dir in [store_dir, binaries_dir] {
+        for file in list_dir(dir)? {
+            let name = file.to_string_lossy();
+            let temp = name.ends_with(TEMP_SUFFIX) || name.ends_with(PART_SUFFIX);
+            if temp && is_older(file.as_path(), grace) {
+                garbage.push(Garbage::new(GarbageKind::TempFile, file));
+            }
+        }
+    }
+
+    for link in list_dir(binaries_dir)? {
+        let target = match read_link(link.as_path()) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let dangling = target.starts_with(stored_binaries_dir.as_path()) && !target.exists();
+        if dangling && !entries.iter().any(|x| x.path == link) {
+            garbage.push(Garbage::new(GarbageKind::DanglingLink, link));
+        }
+    }
+    Ok(garbage)
+}
+
+/// Removes the garbage, and the name dir of a version dir once it is empty.
+pub fn remove(garbage: &Garbage) -> Result<(), TowError> {
+    match garbage.kind {
+        GarbageKind::VersionDir => {
+            remove_dir_all(garbage.path.as_path())
+                .map_err(|e| TowError::io(garbage.path.as_path(), e))?;
+            if let Some(name_dir) = garbage.path.parent() {
+                // fails while other versions are left, which is fine
+                let _ = remove_dir(name_dir);
+            }
+        }
+        _ => remove_file(garbage.path.as_path())
+            .map_err(|e| TowError::io(garbage.path.as_path(), e))?,
+    }
+    Ok(())
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, TowError> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in read_dir(dir).map_err(|e| TowError::io(dir, e))? {
+        paths.push(entry.map_err(|e| TowError::io(dir, e))?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Apparent size, links are not followed.
+fn size_of(path: &Path) -> u64 {
+    match path.symlink_metadata() {
+        Ok(x) if x.is_dir() => list_dir(path)
+            .unwrap_or_default()
+            .iter()
+            .map(|x| size_of(x.as_path()))
+            .sum(),
+        Ok(x) => x.len(),
+        Err(_) => 0,
+    }
+}
+
+fn is_older(path: &Path, age: Duration) -> bool {
+    path.symlink_metadata()
+        .and_then(|x| x.modified())
+        .map(|x| {
+            SystemTime::now()
+                .duration_since(x)
+                .map(|x| x >= age)
+                .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_and_remove() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store_dir = temp_dir.path().join("store");
+        let binaries_dir = temp_dir.path().join("bin");
+        let downloads_dir = store_dir.join("downloads");
+        let stored = store_dir.join(STORED_BINARIES_DIR);
+        for dir in [
+            stored.join("tool").join("v1"),
+            stored.join("tool").join("v2"),
+            stored.join("gone").join("v1"),
+            binaries_dir.to_owned(),
+            downloads_dir.to_owned(),
+        ] {
+            create_dir_all(dir).unwrap();
+        }
+        write(stored.join("tool").join("v1").join("tool"), "v1").unwrap();
+        write(stored.join("tool").join("v2").join("tool"), "v2").unwrap();
+        write(downloads_dir.join("tool.part"), "partial").unwrap();
+        write(binaries_dir.join(".tool.tow-tmp"), "").unwrap();
+        std::os::unix::fs::symlink(
+            stored.join("tool").join("v2").join("tool"),
+            binaries_dir.join("tool"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            stored.join("gone").join("v1").join("gone"),
+            binaries_dir.join("gone"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("/nowhere/mine", binaries_dir.join("mine")).unwrap();
+        let be = BinaryEntry {
+            name: "tool".to_string(),
+            version: "v2".to_string(),
+            path: binaries_dir.join("tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: Some(stored.join("tool").join("v2").join("tool")),
+            sha256: None,
+            asset: None,
+        };
+
+        let find_all = |grace| find(&store_dir, &binaries_dir, &downloads_dir, &[&be], grace);
+        let young = find_all(DEFAULT_GRACE).unwrap();
+        assert_eq!(young.len(), 3);
+        assert!(young.iter().all(|x| x.kind != GarbageKind::Download));
+
+        let garbage = find_all(Duration::ZERO).unwrap();
+        let kinds: Vec<&GarbageKind> = garbage.iter().map(|x| &x.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &GarbageKind::VersionDir,
+                &GarbageKind::VersionDir,
+                &GarbageKind::Download,
+                &GarbageKind::TempFile,
+                &GarbageKind::DanglingLink
+            ]
+        );
+        assert_eq!(garbage[1].path, stored.join("tool").join("v1"));
+        assert_eq!(garbage[1].size, 2);
+
+        for x in &garbage {
+            remove(x).unwrap();
+        }
+        assert!(!stored.join("gone").exists());
+        assert!(stored.join("tool").join("v2").join("tool").is_file());
+        assert!(binaries_dir.join("mine").symlink_metadata().is_ok());
+        assert!(find_all(Duration::ZERO).unwrap().is_empty());
+    }
+}
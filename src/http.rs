@@ -0,0 +1,249 @@
+use log::{debug, warn};
+use reqwest::{Certificate, Method, NoProxy, Proxy, RequestBuilder, Response};
+use std::fs::read;
+use url::Url;
+
+use crate::config::{MirrorConfig, NetworkConfig, S3Config};
+use crate::credentials::Credentials;
+use crate::errors::TowError;
+
+/// Http client shared by downloads and registries, attaches per-host credentials to requests.
+pub struct HttpClient {
+    client: reqwest::Client,
+    credentials: Credentials,
+    s3: S3Config,
+    mirrors: Vec<MirrorConfig>,
+}
+
+impl HttpClient {
+    pub fn new(credentials: Credentials, network: &NetworkConfig) -> Result<Self, TowError> {
+        // disable auto-decompression so that content-length has some meaning
+        // and configure proxies explicitly instead of letting reqwest read env on its own
+        let mut builder = reqwest::Client::builder()
+            .no_deflate()
+            .no_gzip()
+            .no_brotli()
+            .no_proxy();
+
+        let no_proxy = network.no_proxy.as_deref().and_then(NoProxy::from_string);
+        if let Some(proxy) = &network.https_proxy {
+            builder = builder.proxy(Proxy::https(proxy)?.no_proxy(no_proxy.clone()));
+        }
+        if let Some(proxy) = &network.http_proxy {
+            builder = builder.proxy(Proxy::http(proxy)?.no_proxy(no_proxy));
+        }
+        if let Some(ca_cert) = &network.ca_cert {
+            let pem = read(ca_cert).map_err(|e| {
+                TowError::config_with(
+                    &format!("cannot read CA certificate '{}'", ca_cert.display()),
+                    e,
+                )
+            })?;
+            for cert in Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if network.insecure {
+            warn!("TLS certificate verification is disabled");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        let client = builder.build()?;
+        Ok(Self {
+            client,
+            credentials,
+            s3: S3Config::default(),
+            mirrors: Vec::new(),
+        })
+    }
+
+    pub fn with_mirrors(mut self, mirrors: Vec<MirrorConfig>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    pub fn with_s3(mut self, s3: S3Config) -> Self {
+        self.s3 = s3;
+        self
+    }
+
+    pub fn s3(&self) -> &S3Config {
+        &self.s3
+    }
+
+    /// Sends the request to the mirrors of the url in order and finally to the url itself,
+    /// returning the first successful response.
+    pub async fn send(&self, method: Method, url: &Url) -> Result<Response, TowError> {
+        let mut last_error = None;
+        for candidate in self.candidates(url) {
+            if candidate != *url {
+                debug!("trying mirror {} for {}", candidate, url);
+            }
+            let res = self.request(method.to_owned(), &candidate).send().await;
+            match res.and_then(|x| x.error_for_status()) {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    warn!("request to {} failed: {}", candidate, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error
+            .map(|e| e.into())
+            .unwrap_or_else(|| TowError::config(&format!("no urls to try for {}", url))))
+    }
+
+    /// Urls rewritten by the most specific mirror rule, followed by the original url.
+    pub fn candidates(&self, url: &Url) -> Vec<Url> {
+        let mut candidates = Vec::new();
+        let rule = self
+            .mirrors
+            .iter()
+            .filter(|x| url.as_str().starts_with(x.prefix.as_str()))
+            .max_by_key(|x| x.prefix.len());
+        if let Some(rule) = rule {
+            let rest = &url.as_str()[rule.prefix.len()..];
+            for mirror in &rule.urls {
+                match Url::parse(format!("{}{}", mirror, rest).as_str()) {
+                    Ok(x) => candidates.push(x),
+                    Err(e) => warn!("invalid mirror url '{}': {}", mirror, e),
+                }
+            }
+        }
+        candidates.push(url.to_owned());
+        candidates
+    }
+
+    pub fn request(&self, method: Method, url: &Url) -> RequestBuilder {
+        self.credentials
+            .authorize(url, self.client.request(method, url.as_str()))
+    }
+
+    /// Request without per-host credentials, for sources that sign requests themselves.
+    pub fn unauthorized_request(&self, method: Method, url: &Url) -> RequestBuilder {
+        self.client.request(method, url.as_str())
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new(Credentials::default(), &NetworkConfig::default())
+            .expect("cannot build http client")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mockito::{mock, Matcher};
+    use std::io::Write;
+
+    #[test]
+    fn test_http_proxy() {
+        let _m = mock("GET", Matcher::Any)
+            .with_status(200)
+            .with_body("proxied")
+            .create();
+
+        let network = NetworkConfig {
+            http_proxy: Some(mockito::server_url()),
+            ..Default::default()
+        };
+        let http = HttpClient::new(Credentials::default(), &network).unwrap();
+        let url = Url::parse("http://tow.invalid/file").unwrap();
+        let res = tokio_test::block_on(http.request(Method::GET, &url).send()).unwrap();
+        assert_eq!(tokio_test::block_on(res.text()).unwrap(), "proxied");
+        _m.assert();
+    }
+
+    #[test]
+    fn test_candidates() {
+        let http = HttpClient::default().with_mirrors(vec![
+            MirrorConfig {
+                prefix: "https://github.com/".to_string(),
+                urls: vec!["https://artifacts.corp/github-proxy/".to_string()],
+            },
+            MirrorConfig {
+                prefix: "https://github.com/owner/".to_string(),
+                urls: vec![
+                    "https://first.corp/owner/".to_string(),
+                    "https://second.corp/".to_string(),
+                ],
+            },
+        ]);
+
+        let url = Url::parse("https://github.com/other/repo/releases/tool").unwrap();
+        let candidates: Vec<String> = http
+            .candidates(&url)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(
+            candidates,
+            vec![
+                "https://artifacts.corp/github-proxy/other/repo/releases/tool",
+                "https://github.com/other/repo/releases/tool"
+            ]
+        );
+
+        let url = Url::parse("https://github.com/owner/repo/tool").unwrap();
+        let candidates: Vec<String> = http
+            .candidates(&url)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(
+            candidates,
+            vec![
+                "https://first.corp/owner/repo/tool",
+                "https://second.corp/repo/tool",
+                "https://github.com/owner/repo/tool"
+            ]
+        );
+
+        let url = Url::parse("https://gitlab.com/tool").unwrap();
+        assert_eq!(http.candidates(&url), vec![url]);
+    }
+
+    #[test]
+    fn test_send_falls_back_to_next_mirror() {
+        let _broken = mock("GET", "/broken/tool").with_status(500).create();
+        let _working = mock("GET", "/working/tool")
+            .with_status(200)
+            .with_body("mirrored")
+            .create();
+
+        let http = HttpClient::default().with_mirrors(vec![MirrorConfig {
+            prefix: "http://tow.invalid/".to_string(),
+            urls: vec![
+                format!("{}/broken/", mockito::server_url()),
+                format!("{}/working/", mockito::server_url()),
+            ],
+        }]);
+        let url = Url::parse("http://tow.invalid/tool").unwrap();
+        let res = tokio_test::block_on(http.send(Method::GET, &url)).unwrap();
+        assert_eq!(tokio_test::block_on(res.text()).unwrap(), "mirrored");
+        _broken.assert();
+        _working.assert();
+    }
+
+    #[test]
+    fn test_ca_cert() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let ca_cert = temp_dir.path().join("ca.pem");
+        let network = NetworkConfig {
+            ca_cert: Some(ca_cert.to_owned()),
+            ..Default::default()
+        };
+        let err = HttpClient::new(Credentials::default(), &network)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("cannot read CA certificate"));
+
+        std::fs::File::create(&ca_cert)
+            .unwrap()
+            .write_all(b"-----BEGIN CERTIFICATE-----\nnot a cert\n-----END CERTIFICATE-----\n")
+            .unwrap();
+        assert!(HttpClient::new(Credentials::default(), &network).is_err());
+    }
+}
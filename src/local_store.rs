@@ -1,60 +1,80 @@
 use log::{error, info, warn};
 use std::collections::HashMap;
 use std::env;
-use std::fs::{copy, remove_file, rename, File};
+use std::fs::{copy, create_dir_all, read, read_link, remove_dir, rename, File};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use crate::cache;
 use crate::errors::TowError;
-use crate::store::{AddBinaryCmd, BinaryEntry, Hashable, RemoveBinaryCmd, TowStore};
+use crate::files;
+use crate::lock::{StoreLock, DEFAULT_LOCK_TIMEOUT};
+use crate::migrations::{self, SCHEMA_VERSION};
+use crate::store::{
+    check_conflicts, check_word, command_name, AddBinaryCmd, BinaryEntry, Hashable,
+    RemoveBinaryCmd, TowStore,
+};
 
 const STORE_FILENAME: &str = "towstore.json";
 const STORE_BACKUP_FILENAME: &str = ".towstore.json.bak";
+const STORE_BACKUP_COUNT: usize = 5;
+const JOURNAL_FILENAME: &str = ".towstore.journal.json";
+pub const STORED_BINARIES_DIR: &str = "binaries";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LocalTowStore {
+    schema_version: u64,
     binaries: HashMap<String, BinaryEntry>,
     system: String,
     architecture: String,
     binaries_dir: PathBuf,
     store_dir: PathBuf,
+    #[serde(skip, default = "default_lock_timeout")]
+    lock_timeout: Duration,
+}
+
+/// Operation in progress, persisted before touching any file so that an interrupted
+/// operation can be rolled forward or back on the next load.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+enum Transaction {
+    Add { entry: BinaryEntry },
+    Remove { entry: BinaryEntry },
 }
 
 impl TowStore for LocalTowStore {
     fn add_binary(&mut self, add: AddBinaryCmd) -> Result<(), TowError> {
         let be_hash = add.hash();
         if self.binaries.contains_key(be_hash.as_str()) {
-            return Err(TowError::new(
+            return Err(TowError::conflict(
                 format!("{} is already in the store", be_hash).as_str(),
             ));
         }
+        let _lock = self.lock()?;
+        self.reload()?;
+        if self.binaries.contains_key(be_hash.as_str()) {
+            return Err(TowError::conflict(
+                format!("{} was added to the store by another tow process", be_hash).as_str(),
+            ));
+        }
 
-        // move binary to our store
-        let file_location = add.path.as_path();
-        let file_name = file_location
-            .file_name()
-            .and_then(|x| x.to_str())
-            .ok_or_else(|| TowError::new("cannot get filename from file_location"))?;
-        let new_location = self.get_binaries_dir().join(file_name);
-        rename(file_location, new_location.as_path())?;
-
-        // once moved we can add entry
-        let mut be = BinaryEntry::from_add_cmd(add);
-        be.path = new_location;
-        self.binaries.insert(be_hash, be.clone());
-
-        // save but if error then remove
-        match self.save() {
+        let file_location = add.path.to_owned();
+        let force = add.force;
+        let be = prepare_entry(add, self.get_binaries_dir(), self.store_dir.as_path())?;
+        check_conflicts(&be, self.list_binaries().as_slice(), force)?;
+
+        let tx = Transaction::Add { entry: be.clone() };
+        self.begin(&tx)?;
+        match self.apply_add(file_location.as_path(), &be) {
             Err(e) => {
                 error!("error while adding: {}", be);
-                self.remove_binary(RemoveBinaryCmd {
-                    name: be.name,
-                    version: be.version,
-                })?;
+                self.roll_back(&tx);
                 Err(e)
             }
             _ => {
+                self.end()?;
                 info!("added: {} to the store", be);
                 Ok(())
             }
@@ -63,72 +83,315 @@ impl TowStore for LocalTowStore {
 
     fn remove_binary(&mut self, rm: RemoveBinaryCmd) -> Result<(), TowError> {
         let hash = rm.hash();
+        let _lock = self.lock()?;
+        self.reload()?;
 
-        let be = self
-            .binaries
-            .remove(hash.as_str())
-            .ok_or_else(|| TowError::new(format!("{} is not in the store", hash).as_str()))?;
+        let be =
+            self.binaries.get(hash.as_str()).cloned().ok_or_else(|| {
+                TowError::not_found(format!("{} is not in the store", hash).as_str())
+            })?;
 
-        remove_file(be.path.as_path())?;
-
-        match self.save() {
-            Err(e) => {
-                error!("error while saving store: {}", e);
-                Err(e)
-            }
-            _ => {
-                info!("removed: {} from the store", be);
-                Ok(())
-            }
+        let tx = Transaction::Remove { entry: be.clone() };
+        self.begin(&tx)?;
+        self.binaries.remove(hash.as_str());
+        if let Err(e) = self.save() {
+            error!("error while saving store: {}", e);
+            self.binaries.insert(hash, be);
+            self.end()?;
+            return Err(e);
         }
+
+        // the removal is persisted, a failure from now on is rolled forward on the next load
+        remove_entry_files(&be)?;
+        self.end()?;
+        info!("removed: {} from the store", be);
+        Ok(())
     }
 
     fn list_binaries(&self) -> Vec<&BinaryEntry> {
         self.binaries.values().collect()
     }
+
+    fn binaries_dir(&self) -> &Path {
+        self.get_binaries_dir()
+    }
+
+    fn store_dir(&self) -> &Path {
+        self.store_dir.as_path()
+    }
+
+    fn lock_store(&mut self) -> Result<StoreLock, TowError> {
+        let lock = self.lock()?;
+        self.reload()?;
+        Ok(lock)
+    }
 }
 
 impl LocalTowStore {
-    pub fn load_or_create(binaries_dir: &Path, store_dir: &Path) -> Result<Self, TowError> {
-        let store_path_buf = store_dir.join(STORE_FILENAME);
-        let store_path = store_path_buf.as_path();
-        if store_path.is_file() {
-            let mut store = Self::load(store_path)?;
-            store.change_binaries_path_if_needed(binaries_dir);
-            return Ok(store);
+    /// When towstore.json is missing or unreadable and a valid backup exists,
+    /// confirm is asked whether to restore the backup. Other tow processes holding the store
+    /// are waited for up to lock_timeout.
+    pub fn load_or_create(
+        binaries_dir: &Path,
+        store_dir: &Path,
+        lock_timeout: Duration,
+        confirm: &dyn Fn(&str) -> bool,
+    ) -> Result<Self, TowError> {
+        let store_path = store_dir.join(STORE_FILENAME);
+        let mut store = match store_path.is_file() {
+            true => match Self::open(store_path.as_path(), binaries_dir, lock_timeout) {
+                Ok(store) => store,
+                Err(e) if written_by_newer_tow(store_path.as_path()) => return Err(e),
+                Err(e) => {
+                    error!("cannot load {}: {}", store_path.display(), e);
+                    let restored = latest_valid_backup(store_dir).filter(|x| {
+                        confirm(&format!(
+                            "{} is unreadable, restore it from {}?",
+                            store_path.display(),
+                            x.display()
+                        ))
+                    });
+                    match restored {
+                        None => {
+                            return Err(TowError::store_with(
+                                &format!(
+                                "cannot load {} (restore a backup with `tow store restore-backup`)",
+                                store_path.display()
+                            ),
+                                e,
+                            ))
+                        }
+                        Some(backup) => {
+                            restore_backup_file(store_dir, backup.as_path(), lock_timeout)?;
+                            Self::open(store_path.as_path(), binaries_dir, lock_timeout)?
+                        }
+                    }
+                }
+            },
+            false => {
+                let restored = latest_valid_backup(store_dir).filter(|x| {
+                    confirm(&format!(
+                        "{} is missing, restore it from {}?",
+                        store_path.display(),
+                        x.display()
+                    ))
+                });
+                match restored {
+                    None => Self::create(
+                        binaries_dir.to_path_buf(),
+                        store_dir.to_path_buf(),
+                        lock_timeout,
+                    ),
+                    Some(backup) => {
+                        restore_backup_file(store_dir, backup.as_path(), lock_timeout)?;
+                        Self::open(store_path.as_path(), binaries_dir, lock_timeout)?
+                    }
+                }
+            }
+        };
+        if store.get_journal_path().is_file() {
+            let _lock = store.lock()?;
+            store.reload()?;
         }
-        Ok(Self::create(
-            binaries_dir.to_path_buf(),
-            store_dir.to_path_buf(),
-        ))
+        Ok(store)
+    }
+
+    /// Replaces towstore.json with the given backup (or the newest valid one) and
+    /// returns the path of the backup used.
+    pub fn restore_backup(
+        store_dir: &Path,
+        index: Option<usize>,
+        lock_timeout: Duration,
+    ) -> Result<PathBuf, TowError> {
+        let backup = match index {
+            None => latest_valid_backup(store_dir)
+                .ok_or_else(|| TowError::not_found("there is no valid store backup to restore"))?,
+            Some(index) => {
+                let backup = backup_paths(store_dir)
+                    .into_iter()
+                    .nth(index)
+                    .filter(|x| x.is_file())
+                    .ok_or_else(|| {
+                        TowError::not_found(&format!("there is no store backup {}", index))
+                    })?;
+                Self::read(backup.as_path()).map_err(|e| {
+                    TowError::store_with(&format!("invalid backup {}", backup.display()), e)
+                })?;
+                backup
+            }
+        };
+        restore_backup_file(store_dir, backup.as_path(), lock_timeout)?;
+        Ok(backup)
+    }
+
+    /// Backups from the newest, as written by save.
+    pub fn list_backups(store_dir: &Path) -> Vec<PathBuf> {
+        backup_paths(store_dir)
+            .into_iter()
+            .filter(|x| x.is_file())
+            .collect()
+    }
+
+    /// Loads the store and persists a migration right away, so that older tows refuse the file.
+    fn open(
+        store_path: &Path,
+        binaries_dir: &Path,
+        lock_timeout: Duration,
+    ) -> Result<Self, TowError> {
+        let (mut store, migrated) = Self::load(store_path)?;
+        store.lock_timeout = lock_timeout;
+        store.change_binaries_path_if_needed(binaries_dir);
+        if migrated {
+            let _lock = store.lock()?;
+            store.reload()?;
+            store.save()?;
+        }
+        Ok(store)
     }
 
-    fn create(binaries_dir: PathBuf, store_dir: PathBuf) -> Self {
+    fn create(binaries_dir: PathBuf, store_dir: PathBuf, lock_timeout: Duration) -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             binaries_dir,
             store_dir,
             binaries: HashMap::new(),
             system: env::consts::OS.to_string(),
             architecture: env::consts::ARCH.to_string(),
+            lock_timeout,
+        }
+    }
+
+    /// Loads the store, migrating older documents in memory. Returns whether it was migrated,
+    /// in which case the pre-migration file is kept as `.towstore.json.v<version>.bak`.
+    fn load(store_path: &Path) -> Result<(Self, bool), TowError> {
+        let (towstore, version) = Self::read(store_path)?;
+        let migrated = version < SCHEMA_VERSION;
+        if migrated {
+            info!(
+                "migrating {} from schema version {} to {}",
+                store_path.display(),
+                version,
+                SCHEMA_VERSION
+            );
+            create_file_backup(store_path, &migration_backup_filename(version))?;
         }
+        Ok((towstore, migrated))
     }
 
-    fn load(store_path: &Path) -> Result<Self, TowError> {
-        let reader = File::open(store_path)?;
-        let towstore: Self = serde_json::from_reader(reader)?;
-        Ok(towstore)
+    /// Parses and migrates the store file, returning it with its schema version on disk.
+    fn read(store_path: &Path) -> Result<(Self, u64), TowError> {
+        let reader = File::open(store_path).map_err(|e| TowError::io(store_path, e))?;
+        let document: serde_json::Value = serde_json::from_reader(reader)?;
+        let version = migrations::schema_version(&document)?;
+        let towstore: Self = serde_json::from_value(migrations::migrate(document)?)?;
+        Ok((towstore, version))
+    }
+
+    /// Every mutation holds the lock, so that concurrent tow processes do not clobber each other.
+    fn lock(&self) -> Result<StoreLock, TowError> {
+        StoreLock::acquire(self.store_dir.as_path(), self.lock_timeout)
+    }
+
+    /// Picks up changes saved by other processes since load, must be called under the lock.
+    fn reload(&mut self) -> Result<(), TowError> {
+        let store_path = self.get_store_path();
+        if store_path.is_file() {
+            self.binaries = Self::load(store_path.as_path())?.0.binaries;
+        }
+        self.recover()
     }
 
     fn save(&self) -> Result<(), TowError> {
         let store_path = self.get_store_path();
-        if store_path.is_file()
-            && create_file_backup(store_path.as_path(), STORE_BACKUP_FILENAME).is_err()
-        {
+        if store_path.is_file() && rotate_backups(store_path.as_path()).is_err() {
             error!("cannot backup previous towstore file")
         }
-        let writer = File::create(store_path)?;
-        serde_json::to_writer_pretty(writer, self)?;
-        Ok(())
+        files::write_atomically(store_path.as_path(), &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Moves the binary into the store, records it and only then exposes it in binaries_dir.
+    fn apply_add(&mut self, file_location: &Path, be: &BinaryEntry) -> Result<(), TowError> {
+        stage_binary(file_location, be)?;
+        self.binaries.insert(be.hash(), be.clone());
+        self.save()?;
+        expose_binary(be)
+    }
+
+    fn begin(&self, tx: &Transaction) -> Result<(), TowError> {
+        files::write_atomically(
+            self.get_journal_path().as_path(),
+            &serde_json::to_vec_pretty(tx)?,
+        )
+    }
+
+    fn end(&self) -> Result<(), TowError> {
+        files::remove_if_exists(self.get_journal_path().as_path())
+    }
+
+    /// Finishes or reverts the operation interrupted by a crash, if there was one.
+    fn recover(&mut self) -> Result<(), TowError> {
+        let journal_path = self.get_journal_path();
+        if !journal_path.is_file() {
+            return Ok(());
+        }
+        let tx: Transaction = match serde_json::from_reader(
+            File::open(&journal_path).map_err(|e| TowError::io(&journal_path, e))?,
+        ) {
+            Ok(tx) => tx,
+            Err(e) => {
+                // the journal is written atomically, so this is not a crash leftover
+                warn!(
+                    "ignoring unreadable journal {}: {}",
+                    journal_path.display(),
+                    e
+                );
+                return self.end();
+            }
+        };
+        warn!("recovering interrupted operation: {:?}", tx);
+        match &tx {
+            Transaction::Add { entry } => match entry.stored_path.as_deref() {
+                Some(stored_path)
+                    if stored_path.is_file()
+                        && self.binaries.contains_key(entry.hash().as_str()) =>
+                {
+                    files::link_atomically(stored_path, entry.path.as_path())?;
+                    self.end()
+                }
+                _ => {
+                    self.roll_back(&tx);
+                    Ok(())
+                }
+            },
+            Transaction::Remove { entry } => {
+                if !self.binaries.contains_key(entry.hash().as_str()) {
+                    remove_entry_files(entry)?;
+                }
+                self.end()
+            }
+        }
+    }
+
+    /// Reverts an add, leaving no entry and no files behind. Best effort, if anything
+    /// fails the journal stays and the next load tries again.
+    fn roll_back(&mut self, tx: &Transaction) {
+        let entry = match tx {
+            Transaction::Add { entry } => entry,
+            Transaction::Remove { .. } => {
+                let _ = self.end();
+                return;
+            }
+        };
+        let saved = match self.binaries.remove(entry.hash().as_str()) {
+            None => Ok(()),
+            Some(_) => self.save(),
+        };
+        // files can go even if saving failed, recovery drops entries without files
+        let removed = remove_entry_files(entry);
+        match saved.and(removed).and_then(|_| self.end()) {
+            Err(e) => error!("cannot roll back {}, will retry on next load: {}", entry, e),
+            _ => info!("rolled back adding {}", entry),
+        }
     }
 
     fn get_binaries_dir(&self) -> &Path {
@@ -139,6 +402,10 @@ impl LocalTowStore {
         self.store_dir.join(STORE_FILENAME)
     }
 
+    fn get_journal_path(&self) -> PathBuf {
+        self.store_dir.join(JOURNAL_FILENAME)
+    }
+
     fn change_binaries_path_if_needed(&mut self, binaries_dir: &Path) {
         if self.get_binaries_dir() != binaries_dir {
             warn!(
@@ -151,14 +418,191 @@ impl LocalTowStore {
     }
 }
 
+/// Files and dirs of the json store in store_dir: the store, its journal and backups, and the
+/// stored binaries.
+pub fn store_files(store_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![
+        store_dir.join(STORE_FILENAME),
+        store_dir.join(JOURNAL_FILENAME),
+        store_dir.join(STORED_BINARIES_DIR),
+    ];
+    paths.extend(backup_paths(store_dir));
+    paths
+}
+
+/// `.towstore.json.bak` is the newest, then `.towstore.json.bak.1` and so on.
+fn backup_paths(store_dir: &Path) -> Vec<PathBuf> {
+    (0..STORE_BACKUP_COUNT)
+        .map(|x| match x {
+            0 => store_dir.join(STORE_BACKUP_FILENAME),
+            x => store_dir.join(format!("{}.{}", STORE_BACKUP_FILENAME, x)),
+        })
+        .collect()
+}
+
+/// Shifts every backup one place older, dropping the oldest, and backs up the store file.
+fn rotate_backups(store_path: &Path) -> Result<(), TowError> {
+    let backups = backup_paths(parent_dir(store_path));
+    for pair in backups.windows(2).rev() {
+        if pair[0].is_file() {
+            rename(&pair[0], &pair[1]).map_err(|e| TowError::io(&pair[0], e))?;
+        }
+    }
+    create_file_backup(store_path, STORE_BACKUP_FILENAME)
+}
+
+fn latest_valid_backup(store_dir: &Path) -> Option<PathBuf> {
+    LocalTowStore::list_backups(store_dir)
+        .into_iter()
+        .find(|x| match LocalTowStore::read(x.as_path()) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("ignoring invalid backup {}: {}", x.display(), e);
+                false
+            }
+        })
+}
+
+fn restore_backup_file(
+    store_dir: &Path,
+    backup: &Path,
+    lock_timeout: Duration,
+) -> Result<(), TowError> {
+    let _lock = StoreLock::acquire(store_dir, lock_timeout)?;
+    files::write_atomically(
+        store_dir.join(STORE_FILENAME).as_path(),
+        &read(backup).map_err(|e| TowError::io(backup, e))?,
+    )?;
+    warn!("restored the store from {}", backup.display());
+    Ok(())
+}
+
+fn written_by_newer_tow(store_path: &Path) -> bool {
+    File::open(store_path)
+        .ok()
+        .and_then(|x| serde_json::from_reader::<_, serde_json::Value>(x).ok())
+        .and_then(|x| migrations::schema_version(&x).ok())
+        .map(|x| x > SCHEMA_VERSION)
+        .unwrap_or(false)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+fn migration_backup_filename(version: u64) -> String {
+    format!(".{}.v{}.bak", STORE_FILENAME, version)
+}
+
+fn default_lock_timeout() -> Duration {
+    DEFAULT_LOCK_TIMEOUT
+}
+
+/// Entry for the binary at add.path, to be kept in the store dir and exposed in binaries_dir.
+pub fn prepare_entry(
+    add: AddBinaryCmd,
+    binaries_dir: &Path,
+    store_dir: &Path,
+) -> Result<BinaryEntry, TowError> {
+    check_word("name", add.name.as_str())?;
+    check_word("version", add.version.as_str())?;
+    let asset = add
+        .path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| {
+            TowError::config(&format!("'{}' has no utf-8 file name", add.path.display()))
+        })?
+        .to_string();
+    let file_name = match add.file_name.as_deref() {
+        Some(x) => valid_file_name(x)?.to_string(),
+        None => command_name(asset.as_str()),
+    };
+    let sha256 = cache::sha256_file(add.path.as_path())?;
+    let mut be = BinaryEntry::from_add_cmd(add);
+    be.sha256 = Some(sha256);
+    if asset != file_name {
+        be.asset = Some(asset);
+    }
+    be.stored_path =
+        Some(get_version_dir(store_dir, &be.name, &be.version)?.join(file_name.as_str()));
+    be.path = binaries_dir.join(file_name.as_str());
+    Ok(be)
+}
+
+fn valid_file_name(file_name: &str) -> Result<&str, TowError> {
+    match Path::new(file_name).file_name() {
+        Some(x) if x == file_name => Ok(file_name),
+        _ => Err(TowError::config(&format!(
+            "'{}' is not a valid file name",
+            file_name
+        ))),
+    }
+}
+
+/// Absolute, so that links pointing into it work no matter where binaries_dir is.
+fn get_version_dir(store_dir: &Path, name: &str, version: &str) -> Result<PathBuf, TowError> {
+    let store_dir = if store_dir.is_absolute() {
+        store_dir.to_owned()
+    } else {
+        env::current_dir()?.join(store_dir)
+    };
+    Ok(store_dir.join(STORED_BINARIES_DIR).join(name).join(version))
+}
+
+/// Moves the binary into its version dir in the store.
+pub fn stage_binary(file_location: &Path, be: &BinaryEntry) -> Result<(), TowError> {
+    let stored_path = be
+        .stored_path
+        .as_deref()
+        .ok_or_else(|| TowError::store("binary entry has no stored path"))?;
+    if let Some(version_dir) = stored_path.parent() {
+        create_dir_all(version_dir).map_err(|e| TowError::io(version_dir, e))?;
+    }
+    files::move_file(file_location, stored_path)?;
+    files::make_executable(stored_path)
+}
+
+/// Links the stored binary into binaries_dir.
+pub fn expose_binary(be: &BinaryEntry) -> Result<(), TowError> {
+    let stored_path = be
+        .stored_path
+        .as_deref()
+        .ok_or_else(|| TowError::store("binary entry has no stored path"))?;
+    files::link_atomically(stored_path, be.path.as_path())
+}
+
+/// Removes the exposed link (or the file itself for entries from before staging)
+/// and the stored binary with its now empty version dirs.
+pub fn remove_entry_files(be: &BinaryEntry) -> Result<(), TowError> {
+    match be.stored_path.as_deref() {
+        None => files::remove_if_exists(be.path.as_path()),
+        Some(stored_path) => {
+            if read_link(be.path.as_path()).ok().as_deref() == Some(stored_path) {
+                files::remove_if_exists(be.path.as_path())?;
+            }
+            files::remove_if_exists(stored_path)?;
+            let version_dir = stored_path.parent();
+            for dir in [version_dir, version_dir.and_then(|x| x.parent())]
+                .into_iter()
+                .flatten()
+            {
+                // fails if not empty, which is fine
+                let _ = remove_dir(dir);
+            }
+            Ok(())
+        }
+    }
+}
+
 fn create_file_backup(file_path: &Path, backup_file_name: &str) -> Result<(), TowError> {
     if !file_path.is_file() {
-        return Err(TowError::new(
+        return Err(TowError::not_found(
             format!("{} is not a file", file_path.display()).as_str(),
         ));
     }
     let backup = file_path.with_file_name(backup_file_name);
-    copy(file_path, backup)?;
+    copy(file_path, &backup).map_err(|e| TowError::io(&backup, e))?;
     Ok(())
 }
 
@@ -252,12 +696,16 @@ mod test {
         let temp_dir = tempfile::tempdir().unwrap();
         let temp_path = temp_dir.path();
         // setup first store
-        let mut store1 = LocalTowStore::load_or_create(temp_path, temp_path).unwrap();
+        let mut store1 =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+                .unwrap();
         assert_eq!(store1.list_binaries().len(), 0);
         add_fake_binary(&mut store1, FAKE_BINARY_NAME.to_string()).unwrap();
         assert_eq!(store1.list_binaries().len(), 1);
         // after saving it must dump the json file, so anoter load or create must load it
-        let store2 = LocalTowStore::load_or_create(temp_path, temp_path).unwrap();
+        let store2 =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+                .unwrap();
         assert_eq!(store2.list_binaries().len(), 1);
         // add another binary so that backup is created
         add_fake_binary(&mut store1, "fake123".to_string()).unwrap();
@@ -269,6 +717,347 @@ mod test {
         assert!(backup_store_file.is_file());
     }
 
+    #[test]
+    fn test_add_binary_is_staged_and_linked() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+
+        let be = store.list_binaries()[0].clone();
+        let stored_path = be.stored_path.unwrap();
+        assert!(stored_path.starts_with(temp_path.join(STORED_BINARIES_DIR)));
+        assert!(stored_path.is_file());
+        assert_eq!(read_link(be.path.as_path()).unwrap(), stored_path);
+        assert!(!store.get_journal_path().exists());
+
+        store
+            .remove_binary(RemoveBinaryCmd::new(
+                FAKE_BINARY_NAME.to_string(),
+                FAKE_BINARY_VERSION.to_string(),
+            ))
+            .unwrap();
+        assert!(!be.path.exists());
+        assert!(!stored_path.exists());
+        assert!(!temp_path
+            .join(STORED_BINARIES_DIR)
+            .join(FAKE_BINARY_NAME)
+            .exists());
+    }
+
+    #[test]
+    fn test_failed_save_rolls_back_add() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        // a directory in place of the store file makes saving fail
+        create_dir_all(store.get_store_path()).unwrap();
+
+        assert!(add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).is_err());
+        assert_eq!(store.list_binaries().len(), 0);
+        assert!(!temp_path.join("test.bin").exists());
+        assert!(!temp_path
+            .join(STORED_BINARIES_DIR)
+            .join(FAKE_BINARY_NAME)
+            .exists());
+        // rolling back cannot save either, so the journal stays for the next load
+        assert!(store.get_journal_path().exists());
+
+        remove_dir(store.get_store_path()).unwrap();
+        let store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 0);
+        assert!(!store.get_journal_path().exists());
+    }
+
+    #[test]
+    fn test_recover_interrupted_add() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+        let be = store.list_binaries()[0].clone();
+
+        // crash after staging the binary but before saving the store
+        let mut orphan = be.clone();
+        orphan.name = "orphan".to_string();
+        let orphan_dir = get_version_dir(temp_path, "orphan", FAKE_BINARY_VERSION).unwrap();
+        create_dir_all(orphan_dir.as_path()).unwrap();
+        orphan.stored_path = Some(orphan_dir.join("orphan.bin"));
+        File::create(orphan_dir.join("orphan.bin")).unwrap();
+        store
+            .begin(&Transaction::Add {
+                entry: orphan.clone(),
+            })
+            .unwrap();
+
+        let store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 1);
+        assert!(!orphan_dir.exists());
+        assert!(be.path.is_file());
+        assert!(!store.get_journal_path().exists());
+    }
+
+    #[test]
+    fn test_recover_interrupted_remove() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+        let be = store.list_binaries()[0].clone();
+
+        // crash after saving the store but before removing the files
+        let tx = Transaction::Remove { entry: be.clone() };
+        store.begin(&tx).unwrap();
+        store.binaries.clear();
+        store.save().unwrap();
+
+        let store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 0);
+        assert!(!be.path.exists());
+        assert!(!be.stored_path.unwrap().exists());
+        assert!(!store.get_journal_path().exists());
+    }
+
+    #[test]
+    fn test_save_leaves_no_temp_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+        add_fake_binary(&mut store, "fake123".to_string()).unwrap();
+
+        let leftovers: Vec<PathBuf> = read_dir(temp_path)
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.to_string_lossy().ends_with(".tow-tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_stores_do_not_clobber() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut first = temp_store(temp_path);
+        let mut second = temp_store(temp_path);
+
+        add_fake_binary(&mut first, FAKE_BINARY_NAME.to_string()).unwrap();
+        add_fake_binary(&mut second, "fake123".to_string()).unwrap();
+        assert_eq!(second.list_binaries().len(), 2);
+        assert_eq!(temp_store(temp_path).list_binaries().len(), 2);
+
+        assert!(add_fake_binary(&mut first, "fake123".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_locked_store_times_out() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store =
+            LocalTowStore::load_or_create(temp_path, temp_path, Duration::ZERO, &|_| false)
+                .unwrap();
+
+        let lock = StoreLock::acquire(temp_path, Duration::ZERO).unwrap();
+        let err = add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap_err();
+        assert!(err.to_string().contains("locked by another tow process"));
+        assert_eq!(store.list_binaries().len(), 0);
+
+        drop(lock);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_load_migrates_unversioned_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let store_path = temp_path.join(STORE_FILENAME);
+        let legacy = serde_json::json!({
+            "binaries": {
+                "fake-v1.0.0": {
+                    "name": "fake",
+                    "version": "v1.0.0",
+                    "path": temp_path.join("fake"),
+                    "source": "https://example.com/fake"
+                }
+            },
+            "system": "linux",
+            "architecture": "x86_64",
+            "binaries_dir": temp_path,
+            "store_dir": temp_path
+        });
+        files::write_atomically(&store_path, legacy.to_string().as_bytes()).unwrap();
+
+        let store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 1);
+        let backup = temp_path.join(migration_backup_filename(0));
+        assert_eq!(
+            serde_json::from_reader::<_, serde_json::Value>(File::open(backup).unwrap()).unwrap(),
+            legacy
+        );
+        let saved: serde_json::Value =
+            serde_json::from_reader(File::open(&store_path).unwrap()).unwrap();
+        assert_eq!(saved["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_load_refuses_newer_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let newer = serde_json::json!({ "schema_version": SCHEMA_VERSION + 1 });
+        files::write_atomically(
+            &temp_path.join(STORE_FILENAME),
+            newer.to_string().as_bytes(),
+        )
+        .unwrap();
+
+        let err =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+                .unwrap_err();
+        assert!(err.to_string().contains("written by a newer tow"));
+    }
+
+    #[test]
+    fn test_backups_are_rotated() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        for i in 0..STORE_BACKUP_COUNT + 2 {
+            add_fake_binary(&mut store, format!("fake{}", i)).unwrap();
+        }
+
+        let backups = LocalTowStore::list_backups(temp_path);
+        assert_eq!(backups.len(), STORE_BACKUP_COUNT);
+        // the newest backup is the store before the last add
+        let (newest, _) = LocalTowStore::read(backups[0].as_path()).unwrap();
+        assert_eq!(newest.list_binaries().len(), STORE_BACKUP_COUNT + 1);
+        let (oldest, _) = LocalTowStore::read(backups[STORE_BACKUP_COUNT - 1].as_path()).unwrap();
+        assert_eq!(oldest.list_binaries().len(), 2);
+    }
+
+    #[test]
+    fn test_corrupt_store_is_restored_when_confirmed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+        add_fake_binary(&mut store, "fake123".to_string()).unwrap();
+        files::write_atomically(&store.get_store_path(), b"{ not json").unwrap();
+
+        let err =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+                .unwrap_err();
+        assert!(err.to_string().contains("tow store restore-backup"));
+
+        let store = LocalTowStore::load_or_create(
+            temp_path,
+            temp_path,
+            DEFAULT_LOCK_TIMEOUT,
+            &|question| question.contains("is unreadable"),
+        )
+        .unwrap();
+        assert_eq!(store.list_binaries().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_store_is_restored_when_confirmed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+        add_fake_binary(&mut store, "fake123".to_string()).unwrap();
+        std::fs::remove_file(store.get_store_path()).unwrap();
+
+        assert_eq!(temp_store(temp_path).list_binaries().len(), 0);
+        let store =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| true)
+                .unwrap();
+        assert_eq!(store.list_binaries().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_backup() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        assert!(LocalTowStore::restore_backup(temp_path, None, DEFAULT_LOCK_TIMEOUT).is_err());
+
+        let mut store = temp_store(temp_path);
+        for i in 0..3 {
+            add_fake_binary(&mut store, format!("fake{}", i)).unwrap();
+        }
+        // the newest backup is invalid, so the next one is used
+        files::write_atomically(&temp_path.join(STORE_BACKUP_FILENAME), b"").unwrap();
+        let restored =
+            LocalTowStore::restore_backup(temp_path, None, DEFAULT_LOCK_TIMEOUT).unwrap();
+        assert_eq!(restored, backup_paths(temp_path)[1]);
+        assert_eq!(temp_store(temp_path).list_binaries().len(), 1);
+
+        // waits for other processes no longer than the given timeout
+        let lock = StoreLock::acquire(temp_path, Duration::ZERO).unwrap();
+        let err = LocalTowStore::restore_backup(temp_path, None, Duration::ZERO).unwrap_err();
+        assert!(err.to_string().contains("locked by another tow process"));
+        drop(lock);
+
+        assert!(LocalTowStore::restore_backup(temp_path, Some(0), DEFAULT_LOCK_TIMEOUT).is_err());
+        assert!(LocalTowStore::restore_backup(
+            temp_path,
+            Some(STORE_BACKUP_COUNT),
+            DEFAULT_LOCK_TIMEOUT
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_conflicting_file_names() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME.to_string()).unwrap();
+
+        let download_dir = temp_path.join("downloads");
+        let clashing = |name: &str| {
+            let path = download_dir.join(format!("{}.bin", FAKE_BINARY_NAME));
+            File::create(path.as_path()).unwrap();
+            AddBinaryCmd::new(
+                name.to_string(),
+                FAKE_BINARY_VERSION.to_string(),
+                path,
+                "fake".to_string(),
+            )
+        };
+        let err = store.add_binary(clashing("other")).unwrap_err();
+        assert!(err.to_string().contains("belongs to test"));
+        store
+            .add_binary(clashing("other").with_file_name(Some("other.bin".to_string())))
+            .unwrap();
+        assert!(temp_path.join("other.bin").is_file());
+        assert!(store
+            .add_binary(clashing("third").with_file_name(Some("../third".to_string())))
+            .is_err());
+
+        File::create(temp_path.join("unmanaged.bin")).unwrap();
+        let err = store
+            .add_binary(clashing("third").with_file_name(Some("unmanaged.bin".to_string())))
+            .unwrap_err();
+        assert!(err.to_string().contains("not managed by tow"));
+        store
+            .add_binary(
+                clashing("third")
+                    .with_file_name(Some("unmanaged.bin".to_string()))
+                    .with_force(true),
+            )
+            .unwrap();
+        assert_eq!(store.list_binaries().len(), 3);
+
+        // names and versions end up in shims and store dirs
+        for name in ["two words", "line\nbreak", "..", "a/b", ""] {
+            assert!(store.add_binary(clashing(name)).is_err());
+        }
+        let mut bad_version = clashing("fourth");
+        bad_version.version = "1.0 rc".to_string();
+        assert!(store.add_binary(bad_version).is_err());
+        assert_eq!(store.list_binaries().len(), 3);
+    }
+
     fn _print_files_in_dir(dir: &Path) {
         let paths = read_dir(dir).unwrap();
 
@@ -278,12 +1067,15 @@ mod test {
     }
 
     fn temp_store(temp_path: &Path) -> LocalTowStore {
-        LocalTowStore::load_or_create(temp_path, temp_path).unwrap()
+        LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+            .unwrap()
     }
 
     fn add_fake_binary(store: &mut LocalTowStore, name: String) -> Result<(), TowError> {
-        // fake binary
-        let fake_binary_path = store.get_binaries_dir().join("test.bin");
+        // fake binary, downloaded somewhere outside of binaries_dir
+        let download_dir = store.store_dir.join("downloads");
+        create_dir_all(download_dir.as_path()).unwrap();
+        let fake_binary_path = download_dir.join(format!("{}.bin", name));
         File::create(fake_binary_path.as_path()).unwrap();
 
         // add to store
@@ -0,0 +1,121 @@
+use fs2::FileExt;
+use log::{debug, info};
+use std::fs::{create_dir_all, read_to_string, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::errors::TowError;
+
+pub const LOCK_FILENAME: &str = ".towstore.lock";
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Advisory lock on a store dir, held until dropped. The lock file keeps the pid
+/// of the holder so that a waiting process can say who it is waiting for.
+#[derive(Debug)]
+pub struct StoreLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl StoreLock {
+    /// Waits up to timeout for other tow processes to release the store.
+    pub fn acquire(store_dir: &Path, timeout: Duration) -> Result<Self, TowError> {
+        create_dir_all(store_dir).map_err(|e| TowError::io(store_dir, e))?;
+        let path = store_dir.join(LOCK_FILENAME);
+        // no truncate, the pid of the current holder must stay readable
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| TowError::io(&path, e))?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        while file.try_lock_exclusive().is_err() {
+            if start.elapsed() >= timeout {
+                // the lock goes away with its holder, so the holder is still running: removing
+                // the file would let the next process lock a new file while it keeps going
+                return Err(TowError::store(&format!(
+                    "the store is locked by another tow process ({}), gave up waiting after {}s; wait for it to finish or raise store.lock_timeout (TOW_LOCK_TIMEOUT)",
+                    holder(path.as_path()),
+                    timeout.as_secs(),
+                )));
+            }
+            if !waiting {
+                info!(
+                    "waiting for another tow process ({}) to finish",
+                    holder(path.as_path())
+                );
+                waiting = true;
+            }
+            sleep(RETRY_INTERVAL);
+        }
+
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| write!(file, "{}", process::id()))
+            .and_then(|_| file.flush())
+            .map_err(|e| TowError::io(&path, e))?;
+        debug!("locked {}", path.display());
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // the file itself stays, removing it would race with processes about to lock it
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+        debug!("unlocked {}", self.path.display());
+    }
+}
+
+fn holder(path: &Path) -> String {
+    match read_to_string(path).map(|x| x.trim().parse::<u32>()) {
+        Ok(Ok(pid)) => format!("pid {}", pid),
+        _ => "unknown pid".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let lock = StoreLock::acquire(temp_dir.path(), Duration::ZERO).unwrap();
+        assert_eq!(
+            read_to_string(temp_dir.path().join(LOCK_FILENAME)).unwrap(),
+            process::id().to_string()
+        );
+
+        let err = StoreLock::acquire(temp_dir.path(), Duration::from_millis(200)).unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "locked by another tow process (pid {})",
+            process::id()
+        )));
+        assert!(!err.to_string().contains("remove"));
+
+        drop(lock);
+        assert!(StoreLock::acquire(temp_dir.path(), Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_lock_waits_for_release() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let lock = StoreLock::acquire(temp_dir.path(), Duration::ZERO).unwrap();
+        let releaser = std::thread::spawn(move || {
+            sleep(Duration::from_millis(200));
+            drop(lock);
+        });
+        assert!(StoreLock::acquire(temp_dir.path(), Duration::from_secs(10)).is_ok());
+        releaser.join().unwrap();
+    }
+}
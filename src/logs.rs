@@ -1,13 +1,231 @@
-pub fn init(verbosity: usize) {
-    stderrlog::new()
-        .quiet(false)
-        .timestamp(stderrlog::Timestamp::Millisecond)
-        // 0 => LevelFilter::Error,
-        // 1 => LevelFilter::Warn,
-        // 2 => LevelFilter::Info,
-        // 3 => LevelFilter::Debug,
-        // _ => LevelFilter::Trace,
-        .verbosity(verbosity)
-        .init()
-        .unwrap();
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::errors::TowError;
+
+pub const TOW_LOG_ENV: &str = "TOW_LOG";
+pub const TOW_LOG_FILE_ENV: &str = "TOW_LOG_FILE";
+pub const LOG_FOLDER_NAME: &str = "logs";
+const LOG_FILENAME: &str = "tow.log";
+/// tow.log is rotated to tow.log.1 once it grows over this size, shifting the older files up
+/// by one; tow.log.2 is renamed over tow.log.3, so the oldest log is overwritten.
+const LOG_FILE_MAX_SIZE: u64 = 1024 * 1024;
+const LOG_FILE_COUNT: usize = 3;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Level of the messages on stderr: `-q` turns them off, every `-v` shows one more level,
+/// otherwise TOW_LOG decides and warnings are the default.
+pub fn stderr_level(verbose: usize, quiet: bool) -> Result<LevelFilter, TowError> {
+    if quiet {
+        return Ok(LevelFilter::Off);
+    }
+    let level = match (verbose, std::env::var(TOW_LOG_ENV)) {
+        (0, Ok(x)) => parse_level(x.as_str())?,
+        (0, Err(_)) => LevelFilter::Warn,
+        (1, _) => LevelFilter::Info,
+        (2, _) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    Ok(level)
+}
+
+/// Level of the messages in the log file of the store dir, the file is only written
+/// when TOW_LOG_FILE is set.
+pub fn file_level() -> Result<Option<LevelFilter>, TowError> {
+    match std::env::var(TOW_LOG_FILE_ENV) {
+        Err(_) => Ok(None),
+        Ok(x) => parse_level(x.as_str()).map(Some),
+    }
+}
+
+/// off, error, warn, info, debug or trace.
+pub fn parse_level(s: &str) -> Result<LevelFilter, TowError> {
+    s.trim().parse::<LevelFilter>().map_err(|_| {
+        TowError::config(&format!(
+            "unknown log level '{}', expected off, error, warn, info, debug or trace",
+            s
+        ))
+    })
+}
+
+/// Logs to stderr and, if file is given, to a rotating file in its dir.
+pub fn init(stderr_level: LevelFilter, file: Option<(&Path, LevelFilter)>) {
+    QUIET.store(stderr_level == LevelFilter::Off, Ordering::Relaxed);
+    let mut stderr = stderrlog::new();
+    stderr
+        .quiet(stderr_level == LevelFilter::Off)
+        .verbosity(verbosity(stderr_level))
+        .color(match io::stderr().is_terminal() {
+            true => stderrlog::ColorChoice::Auto,
+            false => stderrlog::ColorChoice::Never,
+        });
+    // a log file that cannot be opened must not keep tow from running
+    let file = file.and_then(|(dir, level)| match LogFile::open(dir) {
+        Ok(x) => Some((Mutex::new(x), level)),
+        Err(e) => {
+            eprintln!("cannot open the log file in {}: {}", dir.display(), e);
+            None
+        }
+    });
+    let max_level = file
+        .as_ref()
+        .map(|x| x.1.max(stderr_level))
+        .unwrap_or(stderr_level);
+    let logger = TowLogger {
+        stderr,
+        stderr_level,
+        file,
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Progress bars are drawn only for people watching a terminal, and not with `-q`.
+pub fn show_progress() -> bool {
+    !QUIET.load(Ordering::Relaxed) && io::stderr().is_terminal()
+}
+
+struct TowLogger {
+    stderr: stderrlog::StdErrLog,
+    stderr_level: LevelFilter,
+    file: Option<(Mutex<LogFile>, LevelFilter)>,
+}
+
+impl Log for TowLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.stderr_level
+            || self
+                .file
+                .as_ref()
+                .map(|x| metadata.level() <= x.1)
+                .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.stderr_level {
+            self.stderr.log(record);
+        }
+        if let Some((file, level)) = self.file.as_ref() {
+            if record.level() <= *level {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_line(
+                        format!(
+                            "{} - {} - {} - {}",
+                            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                            record.level(),
+                            record.target(),
+                            record.args()
+                        )
+                        .as_str(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(Ok(mut file)) = self.file.as_ref().map(|x| x.0.lock()) {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// stderrlog counts levels from error.
+fn verbosity(level: LevelFilter) -> usize {
+    match level {
+        LevelFilter::Off | LevelFilter::Error => 0,
+        LevelFilter::Warn => 1,
+        LevelFilter::Info => 2,
+        LevelFilter::Debug => 3,
+        LevelFilter::Trace => 4,
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(dir: &Path) -> Result<Self, TowError> {
+        create_dir_all(dir).map_err(|e| TowError::io(dir, e))?;
+        let path = dir.join(LOG_FILENAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| TowError::io(&path, e))?;
+        let size = file.metadata().map_err(|e| TowError::io(&path, e))?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), TowError> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > LOG_FILE_MAX_SIZE {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line).map_err(|e| TowError::io(&self.path, e))?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// tow.log.2 becomes tow.log.3, tow.log.1 becomes tow.log.2, tow.log becomes tow.log.1.
+    fn rotate(&mut self) -> Result<(), TowError> {
+        for i in (1..LOG_FILE_COUNT).rev() {
+            let from = rotated_path(self.path.as_path(), i);
+            if from.is_file() {
+                rename(&from, rotated_path(self.path.as_path(), i + 1))
+                    .map_err(|e| TowError::io(&from, e))?;
+            }
+        }
+        rename(&self.path, rotated_path(self.path.as_path(), 1))
+            .map_err(|e| TowError::io(&self.path, e))?;
+        self.file = File::create(&self.path).map_err(|e| TowError::io(&self.path, e))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("debug").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level(" OFF ").unwrap(), LevelFilter::Off);
+        assert!(parse_level("loud").is_err());
+        assert_eq!(stderr_level(0, true).unwrap(), LevelFilter::Off);
+        assert_eq!(stderr_level(2, false).unwrap(), LevelFilter::Debug);
+        assert_eq!(stderr_level(5, false).unwrap(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_log_file_rotation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut file = LogFile::open(temp_dir.path()).unwrap();
+        let line = "x".repeat(1023);
+        for _ in 0..(LOG_FILE_COUNT + 1) * 1024 {
+            file.write_line(line.as_str()).unwrap();
+        }
+        let log_path = temp_dir.path().join(LOG_FILENAME);
+        assert!(log_path.metadata().unwrap().len() <= LOG_FILE_MAX_SIZE);
+        for i in 1..=LOG_FILE_COUNT {
+            assert!(rotated_path(log_path.as_path(), i).is_file());
+        }
+        assert!(!rotated_path(log_path.as_path(), LOG_FILE_COUNT + 1).exists());
+    }
 }
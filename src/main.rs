@@ -1,16 +1,31 @@
+mod adopt;
 mod app;
+mod cache;
 mod cli;
+mod config;
+mod credentials;
+mod doctor;
 mod download;
 mod errors;
+mod files;
+mod gc;
+mod http;
 mod local_store;
+mod lock;
 mod logs;
+mod migrations;
+mod output;
+mod profile;
+mod project;
 mod registry;
+mod s3;
+mod shim;
+mod source;
+mod sqlite_store;
 mod store;
+mod verify;
 
 #[tokio::main]
 async fn main() {
-    logs::init(3);
-
-    let app = app::App::new_from_env().expect("cannot start the application");
-    cli::run_cli(app).await
+    cli::run_cli().await
 }
@@ -0,0 +1,135 @@
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use crate::cache;
+use crate::errors::TowError;
+
+/// Version of the towstore.json layout written by this tow, bump it with every new migration.
+pub const SCHEMA_VERSION: u64 = 2;
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), TowError>;
+
+/// `MIGRATIONS[n]` upgrades a document from version n to n + 1.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// Stores written before versioning have no schema_version and count as version 0.
+pub fn schema_version(document: &Value) -> Result<u64, TowError> {
+    match document.get(SCHEMA_VERSION_KEY) {
+        None => Ok(0),
+        Some(x) => x
+            .as_u64()
+            .ok_or_else(|| TowError::store(&format!("invalid {}: {}", SCHEMA_VERSION_KEY, x))),
+    }
+}
+
+/// Upgrades the document step by step to SCHEMA_VERSION.
+/// Documents written by a newer tow are refused, they may hold data this one would drop.
+pub fn migrate(mut document: Value) -> Result<Value, TowError> {
+    let version = schema_version(&document)?;
+    if version > SCHEMA_VERSION {
+        return Err(TowError::store(&format!(
+            "the store was written by a newer tow (schema version {}, this tow supports up to {}), please upgrade tow",
+            version, SCHEMA_VERSION
+        )));
+    }
+    let object = document
+        .as_object_mut()
+        .ok_or_else(|| TowError::store("the store is not a json object"))?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(object).map_err(|e| {
+            TowError::store_with(
+                &format!(
+                    "cannot migrate the store from schema version {} to {}",
+                    from,
+                    from + 1
+                ),
+                e,
+            )
+        })?;
+        object.insert(SCHEMA_VERSION_KEY.to_string(), Value::from(from as u64 + 1));
+    }
+    Ok(document)
+}
+
+/// Unversioned stores could be written without any binaries.
+fn v0_to_v1(document: &mut Map<String, Value>) -> Result<(), TowError> {
+    match document.get("binaries") {
+        None | Some(Value::Null) => {
+            document.insert("binaries".to_string(), Value::Object(Map::new()));
+            Ok(())
+        }
+        Some(Value::Object(_)) => Ok(()),
+        Some(x) => Err(TowError::store(&format!("unexpected binaries: {}", x))),
+    }
+}
+
+/// Entries record the sha256 of their binary, backfilled from the files installed now.
+fn v1_to_v2(document: &mut Map<String, Value>) -> Result<(), TowError> {
+    let binaries = match document.get_mut("binaries") {
+        Some(Value::Object(x)) => x,
+        _ => return Ok(()),
+    };
+    for entry in binaries.values_mut().filter_map(|x| x.as_object_mut()) {
+        let file = entry
+            .get("stored_path")
+            .and_then(|x| x.as_str())
+            .or_else(|| entry.get("path").and_then(|x| x.as_str()))
+            .map(Path::new);
+        let sha256 = match file {
+            Some(file) if file.is_file() => Value::from(cache::sha256_file(file)?),
+            _ => Value::Null,
+        };
+        entry.insert("sha256".to_string(), sha256);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let document = json!({"system": "linux", "architecture": "x86_64"});
+        assert_eq!(schema_version(&document).unwrap(), 0);
+
+        let migrated = migrate(document).unwrap();
+        assert_eq!(schema_version(&migrated).unwrap(), SCHEMA_VERSION);
+        assert_eq!(migrated["binaries"], json!({}));
+        assert_eq!(migrated["system"], "linux");
+
+        assert!(migrate(json!({"binaries": []})).is_err());
+    }
+
+    #[test]
+    fn test_migrate_backfills_digests() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let binary = temp_dir.path().join("tool");
+        std::fs::write(&binary, "tool").unwrap();
+        let document = json!({
+            "schema_version": 1,
+            "binaries": {
+                "tool-v1": {"path": "/nowhere/tool", "stored_path": binary},
+                "gone-v1": {"path": "/nowhere/gone"}
+            }
+        });
+
+        let migrated = migrate(document).unwrap();
+        assert_eq!(
+            migrated["binaries"]["tool-v1"]["sha256"],
+            cache::sha256_file(&binary).unwrap()
+        );
+        assert!(migrated["binaries"]["gone-v1"]["sha256"].is_null());
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer() {
+        let document = json!({ "schema_version": SCHEMA_VERSION + 1, "binaries": {} });
+        let err = migrate(document).unwrap_err();
+        assert!(err.to_string().contains("written by a newer tow"));
+
+        assert!(migrate(json!({"schema_version": "one"})).is_err());
+    }
+}
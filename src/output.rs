@@ -0,0 +1,321 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::adopt::AdoptCandidate;
+use crate::cache::{self, CacheEntry};
+use crate::config::Setting;
+use crate::doctor::Check;
+use crate::errors::TowError;
+use crate::gc::Garbage;
+use crate::profile::Profile;
+use crate::shim::Shim;
+use crate::store::BinaryEntry;
+use crate::verify::Verification;
+
+/// Version of the json documents, bumped only when fields are renamed or removed.
+pub const JSON_SCHEMA_VERSION: u64 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// one line per item, for people
+    #[default]
+    Plain,
+    /// aligned columns with headers
+    Table,
+    /// one json document, for scripts
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = TowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(OutputFormat::Plain),
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(TowError::config(&format!(
+                "unknown output format '{}', expected plain, table or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// Item printed by the commands in every output format.
+pub trait Record: Serialize + Display {
+    const HEADERS: &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+impl<T: Record> Record for &T {
+    const HEADERS: &'static [&'static str] = T::HEADERS;
+
+    fn row(&self) -> Vec<String> {
+        (*self).row()
+    }
+}
+
+/// Prints the records under key, plain output uses their Display.
+pub fn print<T: Record>(format: OutputFormat, key: &str, records: &[T]) -> Result<(), TowError> {
+    match format {
+        OutputFormat::Plain => {
+            for record in records {
+                println!("{}", record)
+            }
+        }
+        OutputFormat::Table => print!("{}", table(records)),
+        OutputFormat::Json => print_json(json!({ key: records }))?,
+    }
+    Ok(())
+}
+
+/// Prints the fields of document with the schema version, on a single line.
+pub fn print_json(mut document: Value) -> Result<(), TowError> {
+    if let Some(object) = document.as_object_mut() {
+        object.insert("schema_version".to_string(), json!(JSON_SCHEMA_VERSION));
+    }
+    println!("{}", serde_json::to_string(&document)?);
+    Ok(())
+}
+
+/// Columns are padded to their widest cell, the last one is not.
+pub fn table<T: Record>(records: &[T]) -> String {
+    let rows: Vec<Vec<String>> =
+        std::iter::once(T::HEADERS.iter().map(|x| x.to_string()).collect())
+            .chain(records.iter().map(|x| x.row()))
+            .collect();
+    let widths: Vec<usize> = (0..T::HEADERS.len())
+        .map(|i| {
+            rows.iter()
+                .map(|x| x.get(i).map(|x| x.chars().count()).unwrap_or(0))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, x)| match i + 1 == row.len() {
+                true => x.to_owned(),
+                false => format!("{:width$}", x, width = widths[i]),
+            })
+            .collect();
+        table.push_str(cells.join("  ").as_str());
+        table.push('\n');
+    }
+    table
+}
+
+/// Installed binary, with every field present (null when unknown) unlike the store format.
+#[derive(Serialize)]
+pub struct EntryRecord<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub path: &'a Path,
+    pub source: &'a str,
+    pub stored_path: Option<&'a Path>,
+    pub sha256: Option<&'a str>,
+    pub asset: Option<&'a str>,
+    #[serde(skip)]
+    entry: &'a BinaryEntry,
+}
+
+impl<'a> From<&'a BinaryEntry> for EntryRecord<'a> {
+    fn from(be: &'a BinaryEntry) -> Self {
+        Self {
+            name: be.name.as_str(),
+            version: be.version.as_str(),
+            path: be.path.as_path(),
+            source: be.source.as_str(),
+            stored_path: be.stored_path.as_deref(),
+            sha256: be.sha256.as_deref(),
+            asset: be.asset.as_deref(),
+            entry: be,
+        }
+    }
+}
+
+impl Display for EntryRecord<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.entry.fmt(f)
+    }
+}
+
+impl Record for EntryRecord<'_> {
+    const HEADERS: &'static [&'static str] = &["NAME", "VERSION", "PATH", "SOURCE"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.to_string(),
+            self.version.to_string(),
+            self.path.display().to_string(),
+            self.source.to_string(),
+        ]
+    }
+}
+
+impl Record for Verification {
+    const HEADERS: &'static [&'static str] = &["NAME", "VERSION", "PATH", "STATUS"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.to_owned(),
+            self.version.to_owned(),
+            self.path.display().to_string(),
+            self.status.to_string(),
+        ]
+    }
+}
+
+/// Version of a binary in a registry.
+#[derive(Serialize)]
+pub struct ReleaseRecord {
+    pub version: String,
+}
+
+impl Display for ReleaseRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.version.as_str())
+    }
+}
+
+impl Record for ReleaseRecord {
+    const HEADERS: &'static [&'static str] = &["VERSION"];
+
+    fn row(&self) -> Vec<String> {
+        vec![self.version.to_owned()]
+    }
+}
+
+impl Record for CacheEntry {
+    const HEADERS: &'static [&'static str] = &["SHA256", "SIZE", "FILENAME", "URL"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.sha256.to_owned(),
+            cache::format_size(self.size),
+            self.filename.to_owned(),
+            self.url.to_owned(),
+        ]
+    }
+}
+
+impl Record for Garbage {
+    const HEADERS: &'static [&'static str] = &["KIND", "PATH", "SIZE"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.kind.to_string(),
+            self.path.display().to_string(),
+            cache::format_size(self.size),
+        ]
+    }
+}
+
+impl Record for AdoptCandidate {
+    const HEADERS: &'static [&'static str] = &["PATH", "REGISTRY"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.path.display().to_string(),
+            self.registry.to_owned().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Record for Check {
+    const HEADERS: &'static [&'static str] = &["SEVERITY", "MESSAGE"];
+
+    fn row(&self) -> Vec<String> {
+        vec![self.severity.to_string(), self.message.to_owned()]
+    }
+}
+
+impl Record for Setting {
+    const HEADERS: &'static [&'static str] = &["KEY", "VALUE", "ORIGIN"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key.to_owned(),
+            self.value.to_string(),
+            self.origin.to_string(),
+        ]
+    }
+}
+
+impl Record for Profile {
+    const HEADERS: &'static [&'static str] = &["ACTIVE", "NAME", "BINARIES_DIR", "STORE_DIR"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            if self.active { "*" } else { "" }.to_string(),
+            self.name.to_owned(),
+            self.binaries_dir.display().to_string(),
+            self.store_dir.display().to_string(),
+        ]
+    }
+}
+
+impl Record for Shim {
+    const HEADERS: &'static [&'static str] = &["PATH", "NAME", "VERSION"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.path.display().to_string(),
+            self.name.to_owned(),
+            self.version.to_owned(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn entry() -> BinaryEntry {
+        BinaryEntry {
+            name: "tool".to_string(),
+            version: "v1".to_string(),
+            path: PathBuf::from("/home/me/my bin/tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: None,
+            sha256: None,
+            asset: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        assert_eq!("JSON".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_entry_record() {
+        let be = entry();
+        let value = serde_json::to_value(EntryRecord::from(&be)).unwrap();
+        assert_eq!(value["path"], "/home/me/my bin/tool");
+        // absent fields are null, never missing
+        assert!(value["stored_path"].is_null());
+        assert_eq!(value.as_object().unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_table() {
+        let be = entry();
+        let table = table(&[EntryRecord::from(&be)]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "NAME  VERSION  PATH                  SOURCE");
+        assert_eq!(
+            lines[1],
+            "tool  v1       /home/me/my bin/tool  https://example.com/tool"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use log::info;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::{create_dir_all, remove_dir, remove_dir_all, remove_file};
+use std::path::{Path, PathBuf};
+
+use crate::app::{self, App};
+use crate::config::{self, Config, DEFAULT_PROFILE};
+use crate::errors::TowError;
+use crate::{local_store, lock, logs, sqlite_store};
+
+/// Named pair of binaries and store dirs, `[profiles.<name>]` in the config.
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub name: String,
+    pub binaries_dir: PathBuf,
+    pub store_dir: PathBuf,
+    pub active: bool,
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            if self.active { "*" } else { " " },
+            self.name,
+            self.binaries_dir.display(),
+            self.store_dir.display()
+        )
+    }
+}
+
+/// The default profile first, then the configured ones by name.
+pub fn list(config: &Config) -> Result<Vec<Profile>, TowError> {
+    std::iter::once(DEFAULT_PROFILE)
+        .chain(config.profiles.keys().map(|x| x.as_str()))
+        .map(|name| profile(config, name))
+        .collect()
+}
+
+fn profile(config: &Config, name: &str) -> Result<Profile, TowError> {
+    let (binaries_dir, store_dir) = config.profile_dirs(name)?;
+    Ok(Profile {
+        name: name.to_owned(),
+        binaries_dir,
+        store_dir,
+        active: config.active_profile() == name,
+    })
+}
+
+/// Adds the profile to the config file at path and creates its dirs, both default to dirs
+/// under the default store dir.
+pub fn create(
+    path: &Path,
+    config: &mut Config,
+    name: &str,
+    binaries_dir: Option<&Path>,
+    store_dir: Option<&Path>,
+) -> Result<Profile, TowError> {
+    check_name(name)?;
+    if config.profiles.contains_key(name) {
+        return Err(TowError::conflict(&format!(
+            "profile '{}' already exists",
+            name
+        )));
+    }
+    let dirs = config::DirsConfig {
+        binaries: binaries_dir.map(absolute).transpose()?,
+        store: store_dir.map(absolute).transpose()?,
+    };
+    config.profiles.insert(name.to_owned(), dirs);
+    // both dirs are written, so that the profile keeps them if the defaults change
+    let (binaries_dir, store_dir) = config.profile_dirs(name)?;
+    for (key, dir) in [("binaries", binaries_dir), ("store", store_dir)] {
+        config::set(
+            path,
+            format!("profiles.{}.{}", name, key).as_str(),
+            dir.to_string_lossy().as_ref(),
+        )?;
+    }
+    let profile = profile(config, name)?;
+    for dir in [&profile.binaries_dir, &profile.store_dir] {
+        create_dir_all(dir).map_err(|e| TowError::io(dir, e))?;
+    }
+    Ok(profile)
+}
+
+/// Removes the profile from the config file at path. With purge its binaries are uninstalled
+/// and the files tow keeps in its store dir are deleted first, once confirm agrees. Profiles
+/// whose dirs hold the dirs of another profile are not purged.
+pub fn delete(
+    path: &Path,
+    config: &Config,
+    name: &str,
+    purge: bool,
+    confirm: &dyn Fn(&str) -> bool,
+) -> Result<Profile, TowError> {
+    if name == DEFAULT_PROFILE {
+        return Err(TowError::config("the default profile cannot be deleted"));
+    }
+    let profile = profile(config, name)?;
+    if purge {
+        check_not_shared(config, &profile)?;
+        if !confirm(&format!(
+            "uninstall the binaries of profile '{}' from {} and delete its store in {}?",
+            name,
+            profile.binaries_dir.display(),
+            profile.store_dir.display()
+        )) {
+            return Err(TowError::config(&format!(
+                "profile '{}' was not purged, pass --yes to purge it without asking",
+                name
+            )));
+        }
+        purge_profile(config, &profile, confirm)?;
+    }
+    config::unset(path, format!("profiles.{}", name).as_str())?;
+    Ok(profile)
+}
+
+/// Purging a dir that is, or holds, a dir of another profile would break that profile.
+fn check_not_shared(config: &Config, profile: &Profile) -> Result<(), TowError> {
+    let dirs = [&profile.binaries_dir, &profile.store_dir].map(|x| real_path(x));
+    for other in list(config)?.iter().filter(|x| x.name != profile.name) {
+        for other_dir in [&other.binaries_dir, &other.store_dir] {
+            if dirs.iter().any(|x| real_path(other_dir).starts_with(x)) {
+                return Err(TowError::conflict(&format!(
+                    "profile '{}' shares {} with profile '{}', delete it without --purge",
+                    profile.name,
+                    other_dir.display(),
+                    other.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Uninstalls every binary and deletes the files of tow in the store dir, the dirs themselves
+/// are only removed once empty.
+fn purge_profile(
+    config: &Config,
+    profile: &Profile,
+    confirm: &dyn Fn(&str) -> bool,
+) -> Result<(), TowError> {
+    let store_dir = profile.store_dir.as_path();
+    let mut app = App::new_from_dirs(
+        profile.binaries_dir.to_owned(),
+        store_dir.to_path_buf(),
+        config,
+        confirm,
+    )?;
+    let entries: Vec<(String, String)> = app
+        .list()
+        .into_iter()
+        .map(|x| (x.name.to_owned(), x.version.to_owned()))
+        .collect();
+    for (name, version) in entries {
+        app.remove(name, version)?;
+    }
+    drop(app);
+
+    let mut owned = local_store::store_files(store_dir);
+    owned.extend(sqlite_store::store_files(store_dir));
+    owned.extend(
+        [
+            app::TOW_CACHE_FOLDER_NAME,
+            app::TOW_DOWNLOADS_FOLDER_NAME,
+            logs::LOG_FOLDER_NAME,
+            lock::LOCK_FILENAME,
+        ]
+        .map(|x| store_dir.join(x)),
+    );
+    for path in owned {
+        let removed = match path.symlink_metadata() {
+            Ok(x) if x.is_dir() => remove_dir_all(&path),
+            Ok(_) => remove_file(&path),
+            Err(_) => continue,
+        };
+        removed.map_err(|e| TowError::io(&path, e))?;
+    }
+    // not empty when they hold files that are not from tow
+    for dir in [&profile.binaries_dir, &profile.store_dir] {
+        if remove_dir(dir).is_err() && dir.is_dir() {
+            info!("kept {}, it holds files not managed by tow", dir.display());
+        }
+    }
+    Ok(())
+}
+
+fn real_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Profile names are used as toml keys and directory names.
+fn check_name(name: &str) -> Result<(), TowError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid || name == DEFAULT_PROFILE {
+        return Err(TowError::config(&format!(
+            "invalid profile name '{}', use letters, digits, - and _ (and not '{}')",
+            name, DEFAULT_PROFILE
+        )));
+    }
+    Ok(())
+}
+
+fn absolute(dir: &Path) -> Result<PathBuf, TowError> {
+    match dir.is_absolute() {
+        true => Ok(dir.to_path_buf()),
+        false => Ok(std::env::current_dir()?.join(dir)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_create_and_delete() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let store_dir = temp_dir.path().join("work-store");
+        let mut config = Config::default();
+
+        let created = create(&path, &mut config, "work", None, Some(&store_dir)).unwrap();
+        assert_eq!(created.binaries_dir, store_dir.join("bin"));
+        assert!(created.binaries_dir.is_dir());
+        assert!(create(&path, &mut config, "work", None, None).is_err());
+        assert!(create(&path, &mut config, "default", None, None).is_err());
+        assert!(create(&path, &mut config, "a b", None, None).is_err());
+
+        let mut config = Config::parse(std::fs::read_to_string(&path).unwrap().as_str()).unwrap();
+        config.profile = Some("work".to_string());
+        assert_eq!(config.dirs().unwrap().1, store_dir);
+        let profiles = list(&config).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert!(profiles[1].active && !profiles[0].active);
+
+        // nothing changes unless the purge is confirmed
+        assert!(delete(&path, &config, "work", true, &|_| false).is_err());
+        assert!(store_dir.is_dir());
+        delete(&path, &config, "work", true, &|_| true).unwrap();
+        assert!(!store_dir.exists());
+        // no empty [profiles] table is left behind
+        assert!(std::fs::read_to_string(&path).unwrap().trim().is_empty());
+    }
+
+    #[test]
+    fn test_purge_keeps_other_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let project = temp_dir.path().join("project");
+        let mut config = Config::default();
+        create(&path, &mut config, "work", None, Some(&project)).unwrap();
+        std::fs::write(project.join("notes.txt"), "mine").unwrap();
+        std::fs::create_dir_all(project.join("downloads")).unwrap();
+
+        let config = Config::parse(std::fs::read_to_string(&path).unwrap().as_str()).unwrap();
+        delete(&path, &config, "work", true, &|_| true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(project.join("notes.txt")).unwrap(),
+            "mine"
+        );
+        assert!(!project.join("downloads").exists());
+        assert!(!project.join("bin").exists());
+    }
+
+    #[test]
+    fn test_purge_refuses_shared_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let store_dir = temp_dir.path().join("store");
+        let mut config = Config::default();
+        create(&path, &mut config, "work", None, Some(&store_dir)).unwrap();
+        create(&path, &mut config, "home", None, Some(temp_dir.path())).unwrap();
+        create(&path, &mut config, "copy", None, Some(&store_dir)).unwrap();
+
+        let config = Config::parse(std::fs::read_to_string(&path).unwrap().as_str()).unwrap();
+        // holds the dirs of work, shares them with copy
+        for name in ["home", "copy", "work"] {
+            let e = delete(&path, &config, name, true, &|_| true).unwrap_err();
+            assert_eq!(e.exit_code(), 7);
+        }
+        assert!(store_dir.is_dir());
+        // the config entry alone can still go
+        delete(&path, &config, "home", false, &|_| true).unwrap();
+    }
+}
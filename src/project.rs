@@ -0,0 +1,313 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+use crate::app::{App, InstallOptions};
+use crate::errors::TowError;
+use crate::store::{BinaryEntry, TowStore};
+
+pub const PROJECT_FILENAME: &str = "tow.toml";
+const PROJECT_FOLDER_NAME: &str = ".tow";
+const PROJECT_BIN_FOLDER_NAME: &str = "bin";
+const PROJECT_STORE_FOLDER_NAME: &str = "store";
+
+/// Tools of a project, listed in the `tow.toml` at its root and installed into `.tow/`.
+#[derive(Debug)]
+pub struct Project {
+    pub root: PathBuf,
+    pub tools: BTreeMap<String, Tool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ProjectFile {
+    tools: BTreeMap<String, ToolSpec>,
+}
+
+/// `name = "<source>"` or `name = { source = "...", version = "..." }`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ToolSpec {
+    Source(String),
+    Table(Tool),
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tool {
+    /// url or path, like `tow install <source>`
+    pub source: Option<String>,
+    /// registry to install the tool from, the default registry of the config if neither
+    /// source nor registry is given
+    pub registry: Option<String>,
+    /// the latest release for registries, `latest` for sources
+    pub version: Option<String>,
+    /// command name, the name of the tool by default
+    #[serde(rename = "as")]
+    pub command: Option<String>,
+}
+
+/// What a sync changed in `.tow/`.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub installed: Vec<BinaryEntry>,
+    pub removed: Vec<BinaryEntry>,
+}
+
+impl Project {
+    /// The project of the nearest `tow.toml` in dir or its parents.
+    pub fn find(dir: &Path) -> Result<Self, TowError> {
+        let root = dir
+            .ancestors()
+            .find(|x| x.join(PROJECT_FILENAME).is_file())
+            .ok_or_else(|| {
+                TowError::not_found(&format!(
+                    "no {} in {} or its parents",
+                    PROJECT_FILENAME,
+                    dir.display()
+                ))
+            })?;
+        Self::load(root)
+    }
+
+    pub fn load(root: &Path) -> Result<Self, TowError> {
+        let path = root.join(PROJECT_FILENAME);
+        let content = read_to_string(&path).map_err(|e| TowError::io(&path, e))?;
+        let file: ProjectFile = toml::from_str(content.as_str())
+            .map_err(|e| TowError::config_with(&format!("cannot parse {}", path.display()), e))?;
+        let tools = file
+            .tools
+            .into_iter()
+            .map(|(name, spec)| match spec {
+                ToolSpec::Source(source) => (
+                    name,
+                    Tool {
+                        source: Some(source),
+                        ..Default::default()
+                    },
+                ),
+                ToolSpec::Table(tool) => (name, tool),
+            })
+            .collect();
+        Ok(Self {
+            root: root.to_path_buf(),
+            tools,
+        })
+    }
+
+    /// `.tow/bin`, prepended to PATH by `tow exec` and `tow env`.
+    pub fn bin_dir(&self) -> PathBuf {
+        self.root
+            .join(PROJECT_FOLDER_NAME)
+            .join(PROJECT_BIN_FOLDER_NAME)
+    }
+
+    pub fn store_dir(&self) -> PathBuf {
+        self.root
+            .join(PROJECT_FOLDER_NAME)
+            .join(PROJECT_STORE_FOLDER_NAME)
+    }
+
+    /// PATH with the bin dir in front.
+    pub fn path_var(&self) -> Result<OsString, TowError> {
+        let current = env::var_os("PATH").unwrap_or_default();
+        let paths = std::iter::once(self.bin_dir()).chain(env::split_paths(&current));
+        env::join_paths(paths).map_err(|e| TowError::config_with("cannot prepend to PATH", e))
+    }
+
+    /// Installs the tools missing from app (an app over the dirs of the project) and removes
+    /// the binaries that are not listed anymore or are of another version.
+    pub async fn sync<T: TowStore>(
+        &self,
+        app: &mut App<T>,
+        default_registry: Option<&str>,
+    ) -> Result<SyncReport, TowError> {
+        let mut report = SyncReport::default();
+        let entries: Vec<BinaryEntry> = app.list().into_iter().cloned().collect();
+        for (name, tool) in &self.tools {
+            let wanted = |x: &BinaryEntry| {
+                x.name == *name
+                    && tool
+                        .version
+                        .as_ref()
+                        .map(|v| *v == x.version)
+                        .unwrap_or(true)
+            };
+            if entries.iter().any(wanted) {
+                continue;
+            }
+            let options = InstallOptions {
+                force: false,
+                command: Some(tool.command.to_owned().unwrap_or_else(|| name.to_owned())),
+            };
+            let installed = match (&tool.source, tool.registry.as_deref().or(default_registry)) {
+                (Some(source), _) => {
+                    let source = self.resolve_source(source);
+                    app.install(
+                        source.as_str(),
+                        Some(name),
+                        tool.version.as_deref(),
+                        &options,
+                    )
+                    .await?
+                }
+                (None, Some(registry)) => {
+                    app.install_from_registry(registry, name, tool.version.as_deref(), &options)
+                        .await?
+                }
+                (None, None) => {
+                    return Err(TowError::config(&format!(
+                        "{} has no source nor registry in {} and there is no default_registry",
+                        name, PROJECT_FILENAME
+                    )))
+                }
+            };
+            report.installed.push(installed);
+        }
+        for be in entries {
+            let listed = self.tools.contains_key(&be.name);
+            let replaced = report
+                .installed
+                .iter()
+                .any(|x| x.name == be.name && x.version != be.version);
+            if !listed || replaced {
+                app.remove(be.name.to_owned(), be.version.to_owned())?;
+                report.removed.push(be);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Relative paths in tow.toml are relative to the root, whatever dir tow runs in.
+    fn resolve_source(&self, source: &str) -> String {
+        match Url::parse(source) {
+            Err(url::ParseError::RelativeUrlWithoutBase) if Path::new(source).is_relative() => {
+                self.root.join(source).to_string_lossy().to_string()
+            }
+            _ => source.to_string(),
+        }
+    }
+}
+
+/// Shell lines for direnv (`eval "$(tow env)"`) or a shell profile.
+pub fn env_exports(project: &Project) -> String {
+    format!(
+        "export PATH={}:\"$PATH\"\n",
+        shell_quote(project.bin_dir().to_string_lossy().as_ref())
+    )
+}
+
+/// Single quotes, safe for any content in sh-like shells.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn test_find_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        write(
+            root.join(PROJECT_FILENAME),
+            r#"
+            [tools]
+            tool = "https://example.com/tool-v1.0.0-linux-amd64"
+            other = { registry = "s3://bucket/tools", version = "1.2.0", as = "oth" }
+            "#,
+        )
+        .unwrap();
+        let nested = root.join("src").join("deep");
+        create_dir_all(&nested).unwrap();
+
+        let project = Project::find(nested.as_path()).unwrap();
+        assert_eq!(project.root, root);
+        assert_eq!(project.tools.len(), 2);
+        assert_eq!(project.tools["other"].command.as_deref(), Some("oth"));
+        assert_eq!(project.bin_dir(), root.join(".tow").join("bin"));
+        assert!(Project::find(root.parent().unwrap()).is_err());
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_sync() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let sources = root.join("sources");
+        create_dir_all(&sources).unwrap();
+        write(sources.join("tool-linux-amd64"), "v1").unwrap();
+        write(sources.join("old"), "old").unwrap();
+        let project_file = |tools: &str| write(root.join(PROJECT_FILENAME), tools).unwrap();
+        project_file(&format!(
+            "[tools]\ntool = {{ source = '{}', version = 'v1' }}\nold = '{}'\n",
+            sources.join("tool-linux-amd64").display(),
+            sources.join("old").display()
+        ));
+
+        let project = Project::load(root).unwrap();
+        let mut app = App::new_from_dirs(
+            project.bin_dir(),
+            project.store_dir(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+        let report = tokio_test::block_on(project.sync(&mut app, None)).unwrap();
+        assert_eq!(report.installed.len(), 2);
+        assert!(project.bin_dir().join("tool").is_file());
+        // nothing to do the second time
+        let report = tokio_test::block_on(project.sync(&mut app, None)).unwrap();
+        assert!(report.installed.is_empty() && report.removed.is_empty());
+
+        project_file(&format!(
+            "[tools]\ntool = {{ source = '{}', version = 'v2' }}\n",
+            sources.join("tool-linux-amd64").display()
+        ));
+        let project = Project::load(root).unwrap();
+        let report = tokio_test::block_on(project.sync(&mut app, None)).unwrap();
+        assert_eq!(report.installed[0].version, "v2");
+        assert_eq!(report.removed.len(), 2);
+        assert_eq!(app.list().len(), 1);
+        assert!(project.bin_dir().join("tool").is_file());
+    }
+
+    #[test]
+    fn test_sync_relative_source() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let vendor = root.join("vendor");
+        let nested = root.join("src").join("deep");
+        create_dir_all(&vendor).unwrap();
+        create_dir_all(&nested).unwrap();
+        write(vendor.join("tool"), "vendored").unwrap();
+        write(
+            root.join(PROJECT_FILENAME),
+            "[tools]
+tool = \"./vendor/tool\"\n",
+        )
+        .unwrap();
+
+        let project = Project::find(nested.as_path()).unwrap();
+        let mut app = App::new_from_dirs(
+            project.bin_dir(),
+            project.store_dir(),
+            &Config::default(),
+            &|_| false,
+        )
+        .unwrap();
+        let report = tokio_test::block_on(project.sync(&mut app, None)).unwrap();
+        assert_eq!(report.installed.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(project.bin_dir().join("tool")).unwrap(),
+            "vendored"
+        );
+    }
+}
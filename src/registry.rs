@@ -1,9 +1,134 @@
+use async_trait::async_trait;
+use std::cmp::Ordering;
+
 use crate::errors::TowError;
 
-// TODO this is just a template for now
-// will be implemented via Github and Gitlab integrations
-pub trait Registry {
-    fn check_for_update(&self, name: String) -> Result<String, TowError>;
-    fn get_releases(&self, name: String) -> Result<Vec<String>, TowError>;
-    fn get_url(&self, name: String, version: String) -> Result<String, TowError>;
+// will be implemented via Github and Gitlab integrations as well
+#[allow(dead_code)]
+#[async_trait]
+pub trait Registry: Send + Sync {
+    async fn check_for_update(&self, name: String) -> Result<String, TowError>;
+    async fn get_releases(&self, name: String) -> Result<Vec<String>, TowError>;
+    async fn get_url(&self, name: String, version: String) -> Result<String, TowError>;
+}
+
+/// Compares versions like `v1.10.0` and `1.9.0-rc1` by their numeric parts, then lexically.
+/// A pre-release (`-` after the numbers, as in semver) comes before its release.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|x| !x.is_empty())
+            .filter_map(|x| x.parse().ok())
+            .collect()
+    };
+    let (a_release, a_pre) = split_pre_release(a);
+    let (b_release, b_pre) = split_pre_release(b);
+    numbers(a_release)
+        .cmp(&numbers(b_release))
+        .then_with(|| match (a_pre, b_pre) {
+            (None, None) => Ordering::Equal,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(x), Some(y)) => numbers(x).cmp(&numbers(y)),
+        })
+        .then_with(|| a.cmp(b))
+}
+
+/// `1.9.0-rc1` is `1.9.0` and `rc1`; the `-` of `jq-1.7` does not follow a digit.
+fn split_pre_release(version: &str) -> (&str, Option<&str>) {
+    let bytes = version.as_bytes();
+    match (1..bytes.len()).find(|&i| bytes[i] == b'-' && bytes[i - 1].is_ascii_digit()) {
+        Some(i) => (&version[..i], Some(&version[i + 1..])),
+        None => (version, None),
+    }
+}
+
+/// Words naming the os and architecture of this machine in asset names.
+pub fn default_asset_preferences() -> Vec<String> {
+    let os: &[&str] = match std::env::consts::OS {
+        "macos" => &["darwin", "macos", "apple"],
+        os => &[os],
+    };
+    let arch: &[&str] = match std::env::consts::ARCH {
+        "x86_64" => &["x86_64", "amd64", "x64"],
+        "aarch64" => &["aarch64", "arm64"],
+        arch => &[arch],
+    };
+    os.iter().chain(arch).map(|x| x.to_string()).collect()
+}
+
+/// Index of the asset to install among the assets of a release: the one named like the
+/// binary, the only one, or the one matching the preferred words best (earlier words weigh
+/// more). None if that is ambiguous.
+pub fn pick_asset(assets: &[String], name: &str, prefer: &[String]) -> Option<usize> {
+    let file_name = |x: &str| {
+        x.rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    };
+    if let Some(i) = assets
+        .iter()
+        .position(|x| file_name(x) == name.to_ascii_lowercase())
+    {
+        return Some(i);
+    }
+    if assets.len() == 1 {
+        return Some(0);
+    }
+    let scores: Vec<usize> = assets
+        .iter()
+        .map(|x| {
+            let file_name = file_name(x);
+            prefer
+                .iter()
+                .enumerate()
+                .filter(|(_, word)| file_name.contains(word.to_ascii_lowercase().as_str()))
+                .map(|(i, _)| prefer.len() - i)
+                .sum()
+        })
+        .collect();
+    let best = scores.iter().copied().max().unwrap_or(0);
+    match scores.iter().filter(|x| **x == best).count() {
+        1 if best > 0 => scores.iter().position(|x| *x == best),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        let mut versions = vec!["v1.10.0", "v1.9.0", "1.9.1", "v0.1", "v1.9.0-rc1"];
+        versions.sort_by(|a, b| compare_versions(a, b));
+        assert_eq!(
+            versions,
+            vec!["v0.1", "v1.9.0-rc1", "v1.9.0", "1.9.1", "v1.10.0"]
+        );
+        assert_eq!(compare_versions("1.0.0-rc2", "1.0.0-rc10"), Ordering::Less);
+        assert_eq!(compare_versions("jq-1.7.1", "jq-1.7"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_pick_asset() {
+        let prefer: Vec<String> = ["linux", "x86_64", "amd64"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let assets: Vec<String> = [
+            "tools/tool/v1/tool-darwin-amd64",
+            "tools/tool/v1/tool-linux-amd64",
+            "tools/tool/v1/tool-linux-arm64",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+        assert_eq!(pick_asset(&assets, "tool", &prefer), Some(1));
+        assert_eq!(pick_asset(&assets, "tool-linux-arm64", &prefer), Some(2));
+        assert_eq!(pick_asset(&assets, "tool", &[]), None);
+        assert_eq!(pick_asset(&assets[..1], "tool", &[]), Some(0));
+    }
 }
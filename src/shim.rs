@@ -0,0 +1,293 @@
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{read_to_string, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::errors::TowError;
+use crate::files;
+use crate::project::{shell_quote, Project, PROJECT_FILENAME};
+use crate::store::{self, BinaryEntry};
+
+/// Pins versions for a dir and its subdirs, one `<name> <version>` per line.
+pub const VERSION_FILENAME: &str = ".tow-version";
+/// Second line of every shim, followed by the name and version it falls back to.
+const SHIM_MARKER: &str = "# tow shim of";
+/// Shims are a few hundred bytes, binaries are not read further than this.
+const SHIM_MAX_SIZE: u64 = 4096;
+
+/// Script in binaries_dir that runs the version of name pinned for the current dir.
+#[derive(Serialize, Debug)]
+pub struct Shim {
+    pub path: PathBuf,
+    pub name: String,
+    /// version run where nothing is pinned
+    pub version: String,
+}
+
+impl Display for Shim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} {}",
+            self.path.display(),
+            self.name,
+            self.version
+        )
+    }
+}
+
+/// Where a version comes from when running a shim.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub version: String,
+    /// the .tow-version or tow.toml that pins it
+    pub file: PathBuf,
+}
+
+/// Writes the shim of be over the link or shim at its path. The shim calls tow (the running
+/// executable) with the dirs of the store, so that it works whatever the active profile is.
+pub fn write(be: &BinaryEntry, binaries_dir: &Path, store_dir: &Path) -> Result<Shim, TowError> {
+    // the marker line is read back word by word, see read
+    store::check_word("name", be.name.as_str())?;
+    store::check_word("version", be.version.as_str())?;
+    let tow = std::env::current_exe()?;
+    let arg = |x: &Path| shell_quote(x.to_string_lossy().as_ref());
+    let script = format!(
+        "#!/bin/sh\n{} {} {}, see `tow shims`\nexec {} shim-exec --binaries-dir {} --store-dir {} {} {} -- \"$@\"\n",
+        SHIM_MARKER,
+        be.name,
+        be.version,
+        arg(tow.as_path()),
+        arg(binaries_dir),
+        arg(store_dir),
+        shell_quote(be.name.as_str()),
+        shell_quote(be.version.as_str()),
+    );
+    files::write_atomically(be.path.as_path(), script.as_bytes())?;
+    files::make_executable(be.path.as_path())?;
+    Ok(Shim {
+        path: be.path.to_owned(),
+        name: be.name.to_owned(),
+        version: be.version.to_owned(),
+    })
+}
+
+/// The shim at path, None for links, binaries and missing files.
+pub fn read(path: &Path) -> Option<Shim> {
+    let metadata = path.symlink_metadata().ok()?;
+    if !metadata.is_file() || metadata.len() > SHIM_MAX_SIZE {
+        return None;
+    }
+    let mut content = String::new();
+    File::open(path)
+        .ok()?
+        .take(SHIM_MAX_SIZE)
+        .read_to_string(&mut content)
+        .ok()?;
+    let marker = content.lines().nth(1)?.strip_prefix(SHIM_MARKER)?;
+    let mut words = marker
+        .trim_end_matches(", see `tow shims`")
+        .split_whitespace();
+    Some(Shim {
+        path: path.to_path_buf(),
+        name: words.next()?.to_string(),
+        version: words.next()?.to_string(),
+    })
+}
+
+pub fn is_shim(path: &Path) -> bool {
+    read(path).is_some()
+}
+
+/// Version of name pinned by the nearest `.tow-version` or `tow.toml` that mentions it,
+/// looking in dir and then its parents. A `.tow-version` wins over a `tow.toml` in the same dir.
+pub fn pinned_version(dir: &Path, name: &str) -> Result<Option<Pin>, TowError> {
+    for dir in dir.ancestors() {
+        let path = dir.join(VERSION_FILENAME);
+        if path.is_file() {
+            if let Some(version) = read_version_file(path.as_path(), name)? {
+                return Ok(Some(Pin {
+                    version,
+                    file: path,
+                }));
+            }
+        }
+        if dir.join(PROJECT_FILENAME).is_file() {
+            let project = Project::load(dir)?;
+            if let Some(version) = project.tools.get(name).and_then(|x| x.version.to_owned()) {
+                return Ok(Some(Pin {
+                    version,
+                    file: dir.join(PROJECT_FILENAME),
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Blank lines and lines starting with # are skipped.
+fn read_version_file(path: &Path, name: &str) -> Result<Option<String>, TowError> {
+    let content = read_to_string(path).map_err(|e| TowError::io(path, e))?;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(x), Some(version)) if x == name => return Ok(Some(version.to_string())),
+            (Some(_), Some(_)) => continue,
+            _ => {
+                return Err(TowError::config(&format!(
+                    "cannot parse '{}' in {}, expected `<name> <version>`",
+                    line,
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The entry a shim of name runs in dir: the pinned version, else default_version.
+pub fn resolve<'a>(
+    entries: &[&'a BinaryEntry],
+    name: &str,
+    default_version: &str,
+    dir: &Path,
+) -> Result<&'a BinaryEntry, TowError> {
+    let find = |version: &str| {
+        entries
+            .iter()
+            .find(|x| x.name == name && x.version == version && x.stored_path.is_some())
+            .copied()
+    };
+    match pinned_version(dir, name)? {
+        Some(pin) => find(pin.version.as_str()).ok_or_else(|| {
+            TowError::not_found(&format!(
+                "{} {} is pinned in {} but not installed",
+                name,
+                pin.version,
+                pin.file.display()
+            ))
+        }),
+        None => find(default_version).ok_or_else(|| {
+            TowError::not_found(&format!(
+                "{} {} is not installed anymore, run `tow shims rebuild`",
+                name, default_version
+            ))
+        }),
+    }
+}
+
+/// Replaces tow with the stored binary of be, keeping the command name as argv[0].
+#[cfg(unix)]
+pub fn exec(be: &BinaryEntry, args: &[OsString]) -> Result<(), TowError> {
+    use std::os::unix::process::CommandExt;
+    let stored_path = stored_path(be)?;
+    let error = std::process::Command::new(stored_path)
+        .arg0(be.path.file_name().unwrap_or_default())
+        .args(args)
+        .exec();
+    Err(TowError::io(stored_path, error))
+}
+
+#[cfg(not(unix))]
+pub fn exec(be: &BinaryEntry, args: &[OsString]) -> Result<(), TowError> {
+    let stored_path = stored_path(be)?;
+    let status = std::process::Command::new(stored_path)
+        .args(args)
+        .status()
+        .map_err(|e| TowError::io(stored_path, e))?;
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+fn stored_path(be: &BinaryEntry) -> Result<&Path, TowError> {
+    be.stored_path
+        .as_deref()
+        .ok_or_else(|| TowError::store("binary entry has no stored path"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write as write_file};
+
+    fn entry(dir: &Path, version: &str) -> BinaryEntry {
+        BinaryEntry {
+            name: "tool".to_string(),
+            version: version.to_string(),
+            path: dir.join("bin").join("tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: Some(dir.join("store").join(version).join("tool")),
+            sha256: None,
+            asset: None,
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let be = entry(temp_dir.path(), "1.2.0");
+        let shim = write(&be, &temp_dir.path().join("bin"), temp_dir.path()).unwrap();
+        assert_eq!(shim.path, be.path);
+
+        let read_back = read(be.path.as_path()).unwrap();
+        assert_eq!(read_back.name, "tool");
+        assert_eq!(read_back.version, "1.2.0");
+        assert!(read_to_string(&be.path)
+            .unwrap()
+            .contains("shim-exec --binaries-dir"));
+        write_file(temp_dir.path().join("other"), "#!/bin/sh\necho hi\n").unwrap();
+        assert!(!is_shim(temp_dir.path().join("other").as_path()));
+        assert!(!is_shim(temp_dir.path().join("missing").as_path()));
+
+        let injected = entry(temp_dir.path(), "1.0\nrm -rf ~");
+        assert!(write(&injected, &temp_dir.path().join("bin"), temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let (v1, v2) = (entry(root, "1.0.0"), entry(root, "2.0.0"));
+        let entries = [&v1, &v2];
+        let project = root.join("project");
+        let nested = project.join("src");
+        create_dir_all(&nested).unwrap();
+
+        // nothing pinned
+        assert_eq!(
+            resolve(&entries, "tool", "2.0.0", &nested).unwrap().version,
+            "2.0.0"
+        );
+
+        write_file(
+            project.join(PROJECT_FILENAME),
+            "[tools]\ntool = { source = 'https://example.com/tool', version = '1.0.0' }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            resolve(&entries, "tool", "2.0.0", &nested).unwrap().version,
+            "1.0.0"
+        );
+
+        // the nearer file wins, files that do not mention the tool are skipped
+        write_file(nested.join(VERSION_FILENAME), "# pins\nother 3.0.0\n").unwrap();
+        assert_eq!(
+            resolve(&entries, "tool", "2.0.0", &nested).unwrap().version,
+            "1.0.0"
+        );
+        write_file(nested.join(VERSION_FILENAME), "tool 2.0.0\n").unwrap();
+        assert_eq!(
+            resolve(&entries, "tool", "1.0.0", &nested).unwrap().version,
+            "2.0.0"
+        );
+
+        write_file(nested.join(VERSION_FILENAME), "tool 9.9.9\n").unwrap();
+        assert!(resolve(&entries, "tool", "2.0.0", &nested).is_err());
+        write_file(nested.join(VERSION_FILENAME), "tool\n").unwrap();
+        assert!(pinned_version(&nested, "tool").is_err());
+    }
+}
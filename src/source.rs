@@ -0,0 +1,131 @@
+use std::fmt::Display;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+use crate::errors::TowError;
+
+/// Where a binary is installed from.
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Remote(Url),
+    /// absolute path to a local file or directory
+    Local(PathBuf),
+}
+
+impl Source {
+    /// Accepts urls, `file://` urls and local (relative or absolute) paths.
+    pub fn parse(source: &str) -> Result<Self, TowError> {
+        match Url::parse(source) {
+            Ok(url) if url.scheme() == "file" => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| TowError::config(&format!("invalid file url '{}'", source)))?;
+                Self::local(path.as_path())
+            }
+            Ok(url) => Ok(Source::Remote(url)),
+            Err(url::ParseError::RelativeUrlWithoutBase) => Self::local(Path::new(source)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn local(path: &Path) -> Result<Self, TowError> {
+        let absolute = path.canonicalize().map_err(|e| TowError::io(path, e))?;
+        Ok(Source::Local(absolute))
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Remote(url) => write!(f, "{}", url),
+            Source::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Picks the file to install from a local path. For directories it is either the file
+/// named like the binary or the only file inside.
+pub fn resolve_local_file(path: &Path, name: Option<&str>) -> Result<PathBuf, TowError> {
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let mut files = Vec::new();
+    for entry in read_dir(path).map_err(|e| TowError::io(path, e))? {
+        let entry_path = entry.map_err(|e| TowError::io(path, e))?.path();
+        if entry_path.is_file() {
+            files.push(entry_path);
+        }
+    }
+    if let Some(name) = name {
+        if let Some(file) = files
+            .iter()
+            .find(|x| x.file_name().and_then(|x| x.to_str()) == Some(name))
+        {
+            return Ok(file.to_owned());
+        }
+    }
+    match files.len() {
+        1 => Ok(files.remove(0)),
+        0 => Err(TowError::not_found(&format!(
+            "no files to install in '{}'",
+            path.display()
+        ))),
+        _ => Err(TowError::config(&format!(
+            "'{}' contains {} files, cannot decide which one to install",
+            path.display(),
+            files.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_parse() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path().canonicalize().unwrap();
+        let file = temp_path.join("tool");
+        File::create(&file).unwrap();
+
+        assert_eq!(
+            Source::parse("https://example.com/tool").unwrap(),
+            Source::Remote(Url::parse("https://example.com/tool").unwrap())
+        );
+        assert_eq!(
+            Source::parse(Url::from_file_path(&file).unwrap().as_str()).unwrap(),
+            Source::Local(file.to_owned())
+        );
+        assert_eq!(
+            Source::parse(file.to_str().unwrap()).unwrap(),
+            Source::Local(file.to_owned())
+        );
+        assert!(Source::parse("does/not/exist")
+            .unwrap_err()
+            .to_string()
+            .contains("cannot access"));
+    }
+
+    #[test]
+    fn test_resolve_local_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        assert!(resolve_local_file(temp_path, None).is_err());
+
+        File::create(temp_path.join("tool")).unwrap();
+        assert_eq!(
+            resolve_local_file(temp_path, None).unwrap(),
+            temp_path.join("tool")
+        );
+
+        File::create(temp_path.join("other")).unwrap();
+        assert!(resolve_local_file(temp_path, None).is_err());
+        assert_eq!(
+            resolve_local_file(temp_path, Some("other")).unwrap(),
+            temp_path.join("other")
+        );
+    }
+}
@@ -0,0 +1,685 @@
+use log::{error, info, warn};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::errors::TowError;
+use crate::local_store::{self, LocalTowStore};
+use crate::lock::StoreLock;
+use crate::store::{
+    check_conflicts, AddBinaryCmd, BinaryEntry, Hashable, RemoveBinaryCmd, TowStore,
+};
+
+const DATABASE_FILENAME: &str = "towstore.sqlite3";
+const JSON_STORE_FILENAME: &str = "towstore.json";
+
+/// Kept in `PRAGMA user_version`, 0 is a database that was never initialized.
+const SCHEMA_VERSION: i64 = 1;
+
+/// `MIGRATIONS[n]` upgrades the database from version n to n + 1.
+const MIGRATIONS: [&str; SCHEMA_VERSION as usize] = ["
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    -- installed binaries
+    CREATE TABLE entries (
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        source TEXT NOT NULL,
+        path TEXT NOT NULL,
+        stored_path TEXT,
+        installed_at INTEGER NOT NULL,
+        sha256 TEXT,
+        asset TEXT,
+        -- 0 from the commit of an add until its binary is exposed in binaries_dir
+        exposed INTEGER NOT NULL DEFAULT 1,
+        PRIMARY KEY (name, version)
+    );
+    -- files owned by the installed binaries, kind is 'stored' or 'link'
+    CREATE TABLE files (
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        path TEXT NOT NULL,
+        PRIMARY KEY (name, version, kind),
+        FOREIGN KEY (name, version) REFERENCES entries (name, version) ON DELETE CASCADE
+    );
+    CREATE INDEX files_path ON files (path);
+    -- every version ever installed, removed_at is null while it is installed
+    CREATE TABLE versions (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        source TEXT NOT NULL,
+        installed_at INTEGER NOT NULL,
+        removed_at INTEGER
+    );
+    -- append only log of operations, operation is 'add', 'remove' or 'import'
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY,
+        at INTEGER NOT NULL,
+        operation TEXT NOT NULL,
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        source TEXT NOT NULL
+    );
+    "];
+
+/// TowStore kept in a SQLite database, every add and remove is a single transaction
+/// and the install history can be queried with any sqlite client.
+pub struct SqliteTowStore {
+    connection: Connection,
+    binaries: HashMap<String, BinaryEntry>,
+    binaries_dir: PathBuf,
+    store_dir: PathBuf,
+    lock_timeout: Duration,
+}
+
+impl TowStore for SqliteTowStore {
+    fn add_binary(&mut self, add: AddBinaryCmd) -> Result<(), TowError> {
+        let be_hash = add.hash();
+        if self.binaries.contains_key(be_hash.as_str()) {
+            return Err(TowError::conflict(
+                format!("{} is already in the store", be_hash).as_str(),
+            ));
+        }
+        let _lock = self.lock()?;
+        self.reload()?;
+        if self.binaries.contains_key(be_hash.as_str()) {
+            return Err(TowError::conflict(
+                format!("{} was added to the store by another tow process", be_hash).as_str(),
+            ));
+        }
+
+        let file_location = add.path.to_owned();
+        let force = add.force;
+        let be = local_store::prepare_entry(add, &self.binaries_dir, &self.store_dir)?;
+        check_conflicts(&be, self.owners(be.path.as_path())?.as_slice(), force)?;
+        let tx = self
+            .connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        insert_entry(&tx, &be, "add", now())?;
+        // the binary is staged in the store dir before the commit and exposed after it: a crash
+        // before the commit leaves an unreferenced staged file for `tow gc`, a crash after it
+        // leaves an entry that is not marked as exposed, which the next load exposes
+        let committed = local_store::stage_binary(file_location.as_path(), &be)
+            .and_then(|_| tx.commit().map_err(|e| e.into()));
+        if let Err(e) = committed {
+            error!("error while adding: {}", be);
+            discard_files(&be);
+            return Err(e);
+        }
+        if let Err(e) = local_store::expose_binary(&be) {
+            error!("error while exposing: {}", be);
+            let tx = self
+                .connection
+                .transaction_with_behavior(TransactionBehavior::Immediate)?;
+            delete_entry(&tx, &be, now())?;
+            tx.commit()?;
+            discard_files(&be);
+            return Err(e);
+        }
+
+        if let Err(e) = self.mark_exposed(&be) {
+            warn!(
+                "cannot mark {} as exposed, the next load exposes it again: {}",
+                be, e
+            );
+        }
+        info!("added: {} to the store", be);
+        self.binaries.insert(be_hash, be);
+        Ok(())
+    }
+
+    fn remove_binary(&mut self, rm: RemoveBinaryCmd) -> Result<(), TowError> {
+        let hash = rm.hash();
+        let _lock = self.lock()?;
+        self.reload()?;
+
+        let be =
+            self.binaries.get(hash.as_str()).cloned().ok_or_else(|| {
+                TowError::not_found(format!("{} is not in the store", hash).as_str())
+            })?;
+
+        let tx = self
+            .connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        delete_entry(&tx, &be, now())?;
+        tx.commit()?;
+        self.binaries.remove(hash.as_str());
+
+        // the removal is committed, leftovers from a failure here are only unreferenced files
+        local_store::remove_entry_files(&be)?;
+        info!("removed: {} from the store", be);
+        Ok(())
+    }
+
+    fn list_binaries(&self) -> Vec<&BinaryEntry> {
+        self.binaries.values().collect()
+    }
+
+    fn binaries_dir(&self) -> &Path {
+        self.binaries_dir.as_path()
+    }
+
+    fn store_dir(&self) -> &Path {
+        self.store_dir.as_path()
+    }
+
+    fn lock_store(&mut self) -> Result<StoreLock, TowError> {
+        let lock = self.lock()?;
+        self.reload()?;
+        Ok(lock)
+    }
+}
+
+impl SqliteTowStore {
+    /// Opens the database, creating it from towstore.json (if there is one) the first time.
+    /// Other tow processes holding the store are waited for up to lock_timeout.
+    pub fn load_or_create(
+        binaries_dir: &Path,
+        store_dir: &Path,
+        lock_timeout: Duration,
+    ) -> Result<Self, TowError> {
+        create_dir_all(store_dir).map_err(|e| TowError::io(store_dir, e))?;
+        let connection = Connection::open(store_dir.join(DATABASE_FILENAME))?;
+        // sqlite takes the timeout in milliseconds as an i32, rusqlite panics above that
+        connection.busy_timeout(lock_timeout.min(Duration::from_millis(i32::MAX as u64)))?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let mut store = Self {
+            connection,
+            binaries: HashMap::new(),
+            binaries_dir: binaries_dir.to_path_buf(),
+            store_dir: store_dir.to_path_buf(),
+            lock_timeout,
+        };
+
+        // loaded before locking, loading it may take the lock itself
+        let json_store_path = store_dir.join(JSON_STORE_FILENAME);
+        let json_store = match store.schema_version()? == 0 && json_store_path.is_file() {
+            true => Some(LocalTowStore::load_or_create(
+                binaries_dir,
+                store_dir,
+                lock_timeout,
+                &|_| false,
+            )?),
+            false => None,
+        };
+
+        let _lock = store.lock()?;
+        store.migrate(json_store.as_ref())?;
+        store.change_binaries_path_if_needed()?;
+        store.reload()?;
+        store.expose_missing()?;
+        Ok(store)
+    }
+
+    fn schema_version(&self) -> Result<i64, TowError> {
+        Ok(self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    /// Brings the schema up to date, importing json_store into a fresh database.
+    /// Everything happens in one transaction, so a failed import leaves a fresh database behind.
+    fn migrate(&mut self, json_store: Option<&LocalTowStore>) -> Result<(), TowError> {
+        let tx = self
+            .connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version: i64 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(TowError::store(&format!(
+                "the store was written by a newer tow (schema version {}, this tow supports up to {}), please upgrade tow",
+                version, SCHEMA_VERSION
+            )));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            info!(
+                "migrating {} from schema version {} to {}",
+                DATABASE_FILENAME,
+                from,
+                from + 1
+            );
+            tx.execute_batch(migration)?;
+        }
+        if version == 0 {
+            tx.execute(
+                "INSERT INTO meta (key, value) VALUES ('system', ?1), ('architecture', ?2)",
+                params![std::env::consts::OS, std::env::consts::ARCH],
+            )?;
+            if let Some(json_store) = json_store {
+                let binaries = json_store.list_binaries();
+                info!(
+                    "importing {} binaries from {}",
+                    binaries.len(),
+                    JSON_STORE_FILENAME
+                );
+                let at = now();
+                for be in binaries {
+                    insert_entry(&tx, be, "import", at)?;
+                }
+            }
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Every mutation holds the lock, so that concurrent tow processes do not clobber each other.
+    fn lock(&self) -> Result<StoreLock, TowError> {
+        StoreLock::acquire(self.store_dir.as_path(), self.lock_timeout)
+    }
+
+    /// Picks up changes committed by other processes since load.
+    fn reload(&mut self) -> Result<(), TowError> {
+        let mut statement = self.connection.prepare(
+            "SELECT name, version, path, source, stored_path, sha256, asset FROM entries",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(BinaryEntry {
+                name: row.get(0)?,
+                version: row.get(1)?,
+                path: PathBuf::from(row.get::<_, String>(2)?),
+                source: row.get(3)?,
+                stored_path: row.get::<_, Option<String>>(4)?.map(PathBuf::from),
+                sha256: row.get(5)?,
+                asset: row.get(6)?,
+            })
+        })?;
+        let mut binaries = HashMap::new();
+        for be in rows {
+            let be = be?;
+            binaries.insert(be.hash(), be);
+        }
+        self.binaries = binaries;
+        Ok(())
+    }
+
+    /// Entries whose link is at path, by the files table.
+    fn owners(&self, path: &Path) -> Result<Vec<&BinaryEntry>, TowError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, version FROM files WHERE kind = 'link' AND path = ?1")?;
+        let rows = statement.query_map(params![path_to_sql(path)?], |row| {
+            Ok(format!(
+                "{}-{}",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?
+            ))
+        })?;
+        let mut owners = Vec::new();
+        for hash in rows {
+            owners.extend(self.binaries.get(hash?.as_str()));
+        }
+        Ok(owners)
+    }
+
+    /// Exposes the binaries whose add was interrupted between its commit and exposing the
+    /// binary, in the order they were added. Links removed by hand stay removed.
+    fn expose_missing(&self) -> Result<(), TowError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, version FROM entries WHERE exposed = 0 ORDER BY installed_at")?;
+        let rows = statement.query_map([], |row| {
+            Ok(format!(
+                "{}-{}",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?
+            ))
+        })?;
+        for hash in rows {
+            let be = match self.binaries.get(hash?.as_str()) {
+                Some(be) => be,
+                None => continue,
+            };
+            match be.stored_path.as_deref().map(|x| x.is_file()) {
+                Some(true) => {
+                    warn!("exposing {}, its install was interrupted", be);
+                    local_store::expose_binary(be)?;
+                }
+                _ => warn!("cannot expose {}, its stored file is missing", be),
+            }
+            self.mark_exposed(be)?;
+        }
+        Ok(())
+    }
+
+    fn mark_exposed(&self, be: &BinaryEntry) -> Result<(), TowError> {
+        self.connection.execute(
+            "UPDATE entries SET exposed = 1 WHERE name = ?1 AND version = ?2",
+            params![be.name, be.version],
+        )?;
+        Ok(())
+    }
+
+    fn change_binaries_path_if_needed(&self) -> Result<(), TowError> {
+        let binaries_dir = path_to_sql(self.binaries_dir.as_path())?;
+        let previous: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'binaries_dir'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if previous.as_deref() == Some(binaries_dir) {
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            warn!(
+                "changing binaries_dir from '{}' to '{}'",
+                previous, binaries_dir
+            );
+        }
+        self.connection.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('binaries_dir', ?1)",
+            params![binaries_dir],
+        )?;
+        Ok(())
+    }
+}
+
+/// Files of the database in store_dir, including the journals sqlite keeps next to it.
+pub fn store_files(store_dir: &Path) -> Vec<PathBuf> {
+    ["", "-wal", "-shm", "-journal"]
+        .iter()
+        .map(|x| store_dir.join(format!("{}{}", DATABASE_FILENAME, x)))
+        .collect()
+}
+
+/// Records the entry with the files it owns, its history and an event in the transaction.
+fn insert_entry(
+    tx: &Transaction,
+    be: &BinaryEntry,
+    operation: &str,
+    at: u64,
+) -> Result<(), TowError> {
+    let path = path_to_sql(be.path.as_path())?;
+    let stored_path = match be.stored_path.as_deref() {
+        None => None,
+        Some(x) => Some(path_to_sql(x)?),
+    };
+    // imported entries are exposed already, added ones once add_binary gets to it
+    let exposed = operation != "add";
+    tx.execute(
+        "INSERT INTO entries (name, version, source, path, stored_path, installed_at, sha256, asset, exposed)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            be.name,
+            be.version,
+            be.source,
+            path,
+            stored_path,
+            at,
+            be.sha256,
+            be.asset,
+            exposed
+        ],
+    )?;
+    for (kind, file) in [("link", Some(path)), ("stored", stored_path)] {
+        if let Some(file) = file {
+            tx.execute(
+                "INSERT INTO files (name, version, kind, path) VALUES (?1, ?2, ?3, ?4)",
+                params![be.name, be.version, kind, file],
+            )?;
+        }
+    }
+    tx.execute(
+        "INSERT INTO versions (name, version, source, installed_at) VALUES (?1, ?2, ?3, ?4)",
+        params![be.name, be.version, be.source, at],
+    )?;
+    insert_event(tx, be, operation, at)
+}
+
+/// Deletes the entry and its files, closing its history with an event, in the transaction.
+fn delete_entry(tx: &Transaction, be: &BinaryEntry, at: u64) -> Result<(), TowError> {
+    tx.execute(
+        "DELETE FROM entries WHERE name = ?1 AND version = ?2",
+        params![be.name, be.version],
+    )?;
+    tx.execute(
+        "UPDATE versions SET removed_at = ?3
+         WHERE name = ?1 AND version = ?2 AND removed_at IS NULL",
+        params![be.name, be.version, at],
+    )?;
+    insert_event(tx, be, "remove", at)
+}
+
+/// Files of an add that did not make it into the store.
+fn discard_files(be: &BinaryEntry) {
+    if let Err(e) = local_store::remove_entry_files(be) {
+        error!("cannot remove files of {}: {}", be, e);
+    }
+}
+
+fn insert_event(
+    tx: &Transaction,
+    be: &BinaryEntry,
+    operation: &str,
+    at: u64,
+) -> Result<(), TowError> {
+    tx.execute(
+        "INSERT INTO events (at, operation, name, version, source) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![at, operation, be.name, be.version, be.source],
+    )?;
+    Ok(())
+}
+
+fn path_to_sql(path: &Path) -> Result<&str, TowError> {
+    path.to_str()
+        .ok_or_else(|| TowError::config(&format!("non utf-8 path '{}'", path.display())))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lock::DEFAULT_LOCK_TIMEOUT;
+    use std::fs::File;
+
+    const FAKE_BINARY_NAME: &str = "fake";
+    const FAKE_BINARY_VERSION: &str = "v1.0.0";
+
+    #[test]
+    fn test_add_and_remove_binary() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME).unwrap();
+        assert!(add_fake_binary(&mut store, FAKE_BINARY_NAME).is_err());
+        assert_eq!(store.list_binaries().len(), 1);
+        let be = store.list_binaries()[0].clone();
+        assert!(be.path.is_file());
+
+        assert_eq!(count(&store, "SELECT count(*) FROM files"), 2);
+
+        // persisted
+        let mut store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 1);
+
+        store
+            .remove_binary(RemoveBinaryCmd::new(
+                FAKE_BINARY_NAME.to_string(),
+                FAKE_BINARY_VERSION.to_string(),
+            ))
+            .unwrap();
+        assert_eq!(store.list_binaries().len(), 0);
+        assert!(!be.path.exists());
+        assert!(!be.stored_path.unwrap().exists());
+        assert_eq!(count(&store, "SELECT count(*) FROM files"), 0);
+        assert_eq!(
+            count(
+                &store,
+                "SELECT count(*) FROM versions WHERE removed_at IS NOT NULL"
+            ),
+            1
+        );
+        assert_eq!(count(&store, "SELECT count(*) FROM events"), 2);
+    }
+
+    #[test]
+    fn test_files_own_their_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME).unwrap();
+        let be = store.list_binaries()[0].clone();
+
+        // another binary exposed under the same command name
+        let other = AddBinaryCmd::new(
+            "other".to_string(),
+            FAKE_BINARY_VERSION.to_string(),
+            be.stored_path.to_owned().unwrap(),
+            "fake".to_string(),
+        )
+        .with_file_name(be.path.file_name().map(|x| x.to_string_lossy().to_string()));
+        let err = store.add_binary(other).unwrap_err();
+        assert!(err.to_string().contains("belongs to fake"));
+        assert_eq!(
+            count(
+                &store,
+                "SELECT count(*) FROM files WHERE kind = 'link' AND name = 'fake'"
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn test_load_links_interrupted_add() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        add_fake_binary(&mut store, FAKE_BINARY_NAME).unwrap();
+        let be = store.list_binaries()[0].clone();
+        // removed by hand, not tow's to restore
+        std::fs::remove_file(&be.path).unwrap();
+        let store = temp_store(temp_path);
+        assert!(be.path.symlink_metadata().is_err());
+
+        // as if tow stopped right after the commit
+        store
+            .connection
+            .execute("UPDATE entries SET exposed = 0", [])
+            .unwrap();
+        drop(store);
+        let store = temp_store(temp_path);
+        assert_eq!(std::fs::read_link(&be.path).ok(), be.stored_path);
+        assert_eq!(store.list_binaries().len(), 1);
+        assert_eq!(
+            count(&store, "SELECT count(*) FROM entries WHERE exposed = 0"),
+            0
+        );
+    }
+
+    #[test]
+    fn test_failed_add_leaves_nothing() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut store = temp_store(temp_path);
+        let missing = AddBinaryCmd::new(
+            FAKE_BINARY_NAME.to_string(),
+            FAKE_BINARY_VERSION.to_string(),
+            temp_path.join("missing.bin"),
+            "fake".to_string(),
+        );
+        assert!(store.add_binary(missing).is_err());
+        assert_eq!(store.list_binaries().len(), 0);
+        assert_eq!(count(&store, "SELECT count(*) FROM entries"), 0);
+        assert_eq!(count(&store, "SELECT count(*) FROM events"), 0);
+    }
+
+    #[test]
+    fn test_import_json_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let mut json_store =
+            LocalTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT, &|_| false)
+                .unwrap();
+        let download_dir = temp_path.join("downloads");
+        create_dir_all(&download_dir).unwrap();
+        File::create(download_dir.join("test.bin")).unwrap();
+        json_store
+            .add_binary(AddBinaryCmd::new(
+                FAKE_BINARY_NAME.to_string(),
+                FAKE_BINARY_VERSION.to_string(),
+                download_dir.join("test.bin"),
+                "fake".to_string(),
+            ))
+            .unwrap();
+
+        let store = temp_store(temp_path);
+        assert_eq!(store.list_binaries().len(), 1);
+        assert_eq!(
+            count(
+                &store,
+                "SELECT count(*) FROM events WHERE operation = 'import'"
+            ),
+            1
+        );
+        assert_eq!(count(&store, "SELECT count(*) FROM files"), 2);
+        // one-shot, reopening does not import again
+        let store = temp_store(temp_path);
+        assert_eq!(count(&store, "SELECT count(*) FROM versions"), 1);
+    }
+
+    #[test]
+    fn test_long_lock_timeout() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let lock_timeout = crate::cache::parse_duration("3650d").unwrap();
+        assert!(
+            SqliteTowStore::load_or_create(temp_dir.path(), temp_dir.path(), lock_timeout).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_refuses_newer_database() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_path = temp_dir.path();
+        let store = temp_store(temp_path);
+        store
+            .connection
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        drop(store);
+
+        let err = SqliteTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("written by a newer tow"));
+    }
+
+    fn count(store: &SqliteTowStore, query: &str) -> i64 {
+        store
+            .connection
+            .query_row(query, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn temp_store(temp_path: &Path) -> SqliteTowStore {
+        SqliteTowStore::load_or_create(temp_path, temp_path, DEFAULT_LOCK_TIMEOUT).unwrap()
+    }
+
+    fn add_fake_binary(store: &mut SqliteTowStore, name: &str) -> Result<(), TowError> {
+        // fake binary, downloaded somewhere outside of binaries_dir
+        let download_dir = store.store_dir.join("downloads");
+        create_dir_all(download_dir.as_path()).unwrap();
+        let fake_binary_path = download_dir.join(format!("{}.bin", name));
+        File::create(fake_binary_path.as_path()).unwrap();
+
+        store.add_binary(AddBinaryCmd::new(
+            name.to_string(),
+            FAKE_BINARY_VERSION.to_string(),
+            fake_binary_path,
+            "fake".to_string(),
+        ))
+    }
+}
@@ -1,13 +1,51 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::errors::TowError;
+use crate::lock::StoreLock;
 
 pub trait TowStore {
     fn add_binary(&mut self, add: AddBinaryCmd) -> Result<(), TowError>;
     fn remove_binary(&mut self, rm: RemoveBinaryCmd) -> Result<(), TowError>;
     fn list_binaries(&self) -> Vec<&BinaryEntry>;
+    /// where binaries are exposed
+    fn binaries_dir(&self) -> &Path;
+    /// where binaries and the store itself are kept
+    fn store_dir(&self) -> &Path;
+    /// Locks the store dir and picks up changes of other processes, list_binaries stays
+    /// current until the lock is dropped.
+    fn lock_store(&mut self) -> Result<StoreLock, TowError>;
+}
+
+impl<S: TowStore + ?Sized> TowStore for Box<S> {
+    fn add_binary(&mut self, add: AddBinaryCmd) -> Result<(), TowError> {
+        (**self).add_binary(add)
+    }
+
+    fn remove_binary(&mut self, rm: RemoveBinaryCmd) -> Result<(), TowError> {
+        (**self).remove_binary(rm)
+    }
+
+    fn list_binaries(&self) -> Vec<&BinaryEntry> {
+        (**self).list_binaries()
+    }
+
+    fn binaries_dir(&self) -> &Path {
+        (**self).binaries_dir()
+    }
+
+    fn store_dir(&self) -> &Path {
+        (**self).store_dir()
+    }
+
+    fn lock_store(&mut self) -> Result<StoreLock, TowError> {
+        (**self).lock_store()
+    }
 }
 
 pub trait Hashable {
@@ -19,6 +57,10 @@ pub struct AddBinaryCmd {
     pub version: String,
     pub path: PathBuf,
     pub source: String,
+    /// exposes the binary under this command name instead of the one guessed from the filename
+    pub file_name: Option<String>,
+    /// takes over files owned by other entries or not managed by tow
+    pub force: bool,
 }
 
 impl AddBinaryCmd {
@@ -28,8 +70,20 @@ impl AddBinaryCmd {
             version,
             path,
             source,
+            file_name: None,
+            force: false,
         }
     }
+
+    pub fn with_file_name(mut self, file_name: Option<String>) -> Self {
+        self.file_name = file_name;
+        self
+    }
+
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
 }
 
 impl Hashable for AddBinaryCmd {
@@ -59,8 +113,18 @@ impl Hashable for RemoveBinaryCmd {
 pub struct BinaryEntry {
     pub name: String,
     pub version: String,
+    /// where the binary is exposed, in binaries_dir
     pub path: PathBuf,
     pub source: String,
+    /// where the binary is kept in the store, `path` links to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_path: Option<PathBuf>,
+    /// digest of the binary when it was installed, checked by `tow verify`
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// filename of the downloaded asset, when it is exposed under another command name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
 }
 
 impl BinaryEntry {
@@ -70,8 +134,114 @@ impl BinaryEntry {
             version: add.version,
             path: add.path,
             source: add.source,
+            stored_path: None,
+            sha256: None,
+            asset: None,
+        }
+    }
+}
+
+const OS_WORDS: [&str; 13] = [
+    "linux", "darwin", "macos", "apple", "windows", "win", "freebsd", "netbsd", "openbsd",
+    "unknown", "pc", "gnu", "musl",
+];
+const ARCH_WORDS: [&str; 13] = [
+    "x86",
+    "x86_64",
+    "x64",
+    "amd64",
+    "i386",
+    "i686",
+    "386",
+    "aarch64",
+    "arm64",
+    "arm",
+    "armv7",
+    "armhf",
+    "universal",
+];
+
+/// Guesses the command from a release asset filename by dropping everything from the first
+/// version, os or architecture part on, e.g. `tool-v1.2.3-x86_64-unknown-linux-musl` is `tool`.
+pub fn command_name(asset: &str) -> String {
+    let is_suffix = |word: &str| {
+        let lower = word.to_lowercase();
+        let number = lower.strip_prefix('v').unwrap_or(&lower);
+        number.starts_with(|c: char| c.is_ascii_digit())
+            || OS_WORDS.contains(&lower.as_str())
+            || ARCH_WORDS.contains(&lower.as_str())
+    };
+    let mut end = asset.len();
+    let mut start = 0;
+    for (i, c) in asset.char_indices().chain([(asset.len(), '-')]) {
+        if c != '-' && c != '_' {
+            continue;
+        }
+        // "x86_64" is split at '_' too, its "x86" part is enough to recognize it
+        if start > 0 && is_suffix(&asset[start..i]) {
+            end = start - 1;
+            break;
+        }
+        start = i + 1;
+    }
+    asset[..end].to_string()
+}
+
+/// Names and versions are dir names in the store and words in the marker line of shims, so they
+/// must be file names without whitespace or control characters.
+pub fn check_word(kind: &str, value: &str) -> Result<(), TowError> {
+    let is_file_name = Path::new(value).file_name() == Some(value.as_ref());
+    if !is_file_name || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(TowError::config(&format!(
+            "invalid {} '{}', use a file name without spaces",
+            kind,
+            value.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// Refuses to expose be at a path owned by an entry of another binary or by a file
+/// tow does not manage, unless forced. Other versions of the same binary share the path.
+pub fn check_conflicts(
+    be: &BinaryEntry,
+    entries: &[&BinaryEntry],
+    force: bool,
+) -> Result<(), TowError> {
+    let resolve =
+        "pass --force to overwrite it or --as <command-name> to install under another name";
+    if let Some(owner) = entries
+        .iter()
+        .find(|x| x.path == be.path && x.name != be.name)
+    {
+        if !force {
+            return Err(TowError::conflict(&format!(
+                "{} belongs to {} {}, {}",
+                be.path.display(),
+                owner.name,
+                owner.version,
+                resolve
+            )));
         }
+        warn!(
+            "{} {} takes {} over from {} {}",
+            be.name,
+            be.version,
+            be.path.display(),
+            owner.name,
+            owner.version
+        );
+    } else if be.path.symlink_metadata().is_ok() && !entries.iter().any(|x| x.path == be.path) {
+        if !force {
+            return Err(TowError::conflict(&format!(
+                "{} exists and is not managed by tow, {}",
+                be.path.display(),
+                resolve
+            )));
+        }
+        warn!("overwriting {}, not managed by tow", be.path.display());
     }
+    Ok(())
 }
 
 impl Hashable for BinaryEntry {
@@ -91,3 +261,24 @@ impl Display for BinaryEntry {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_command_name() {
+        for (asset, command) in [
+            ("tool-v1.2.3-x86_64-unknown-linux-musl", "tool"),
+            ("tool_1.2.3_linux_amd64", "tool"),
+            ("my-tool-aarch64-apple-darwin", "my-tool"),
+            ("tool-linux", "tool"),
+            ("tool", "tool"),
+            ("test.txt", "test.txt"),
+            ("linux-tool", "linux-tool"),
+            ("docker-compose-Linux-x86_64", "docker-compose"),
+        ] {
+            assert_eq!(command_name(asset), command, "{}", asset);
+        }
+    }
+}
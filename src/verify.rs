@@ -0,0 +1,187 @@
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::read_link;
+use std::path::PathBuf;
+
+use crate::cache;
+use crate::registry::compare_versions;
+use crate::shim;
+use crate::store::BinaryEntry;
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum FileStatus {
+    Ok,
+    /// content differs from the digest recorded at install
+    Modified {
+        expected: String,
+        actual: String,
+    },
+    Missing,
+    /// the exposed path is no longer the link to the stored binary
+    Replaced,
+    /// installed before digests were recorded
+    Unrecorded,
+    Unreadable {
+        error: String,
+    },
+}
+
+impl Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileStatus::Ok => f.write_str("ok"),
+            FileStatus::Modified { expected, actual } => f.write_fmt(format_args!(
+                "modified (expected sha256 {}, got {})",
+                expected, actual
+            )),
+            FileStatus::Missing => f.write_str("missing"),
+            FileStatus::Replaced => f.write_str("replaced, no longer links to the stored binary"),
+            FileStatus::Unrecorded => f.write_str("unverified, no digest recorded"),
+            FileStatus::Unreadable { error } => f.write_fmt(format_args!("unreadable ({})", error)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Verification {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub status: FileStatus,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.status, FileStatus::Ok | FileStatus::Unrecorded)
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} {} {}: {}",
+            self.name,
+            self.version,
+            self.path.display(),
+            self.status
+        ))
+    }
+}
+
+/// Re-hashes the binary of the entry against the recorded digest and checks its link. The
+/// versions of a binary share one link, it is checked with the version it points to, or the
+/// newest one if it points to none of them.
+pub fn verify_entry(be: &BinaryEntry, entries: &[&BinaryEntry]) -> Vec<Verification> {
+    let verification = |path: PathBuf, status| Verification {
+        name: be.name.to_owned(),
+        version: be.version.to_owned(),
+        path,
+        status,
+    };
+    let file = be
+        .stored_path
+        .to_owned()
+        .unwrap_or_else(|| be.path.to_owned());
+    let file_status = if !file.is_file() {
+        FileStatus::Missing
+    } else {
+        match (&be.sha256, cache::sha256_file(file.as_path())) {
+            (_, Err(e)) => FileStatus::Unreadable {
+                error: e.to_string(),
+            },
+            (None, Ok(_)) => FileStatus::Unrecorded,
+            (Some(expected), Ok(actual)) if *expected == actual => FileStatus::Ok,
+            (Some(expected), Ok(actual)) => FileStatus::Modified {
+                expected: expected.to_owned(),
+                actual,
+            },
+        }
+    };
+    let mut verifications = vec![verification(file, file_status)];
+
+    let owns_link = link_owner(be, entries).map(|x| x.version == be.version);
+    if be.stored_path.is_some() && owns_link == Some(true) {
+        let link_status = if is_tracked(be, entries) {
+            FileStatus::Ok
+        } else if be.path.symlink_metadata().is_ok() {
+            FileStatus::Replaced
+        } else {
+            FileStatus::Missing
+        };
+        verifications.push(verification(be.path.to_owned(), link_status));
+    }
+    verifications
+}
+
+/// Whether the path of be is the link to a stored version of its binary, or its shim.
+pub fn is_tracked(be: &BinaryEntry, entries: &[&BinaryEntry]) -> bool {
+    match read_link(be.path.as_path()) {
+        Ok(target) => versions(be, entries).any(|x| x.stored_path.as_ref() == Some(&target)),
+        Err(_) => shim::read(be.path.as_path()).map(|x| x.name) == Some(be.name.to_owned()),
+    }
+}
+
+/// The version of be the link at its path points to, else the newest one.
+fn link_owner<'a>(be: &'a BinaryEntry, entries: &[&'a BinaryEntry]) -> Option<&'a BinaryEntry> {
+    let target = read_link(be.path.as_path()).ok();
+    versions(be, entries)
+        .find(|x| target.is_some() && x.stored_path == target)
+        .or_else(|| versions(be, entries).max_by(|a, b| compare_versions(&a.version, &b.version)))
+}
+
+/// Entries of the binary of be exposed at the same path, be included.
+fn versions<'a>(
+    be: &'a BinaryEntry,
+    entries: &[&'a BinaryEntry],
+) -> impl Iterator<Item = &'a BinaryEntry> {
+    let others: Vec<&BinaryEntry> = entries
+        .iter()
+        .copied()
+        .filter(|x| x.name == be.name && x.path == be.path && x.stored_path.is_some())
+        .filter(|x| x.version != be.version)
+        .collect();
+    std::iter::once(be).chain(others)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::write;
+
+    #[test]
+    fn test_verify_entry() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stored_path = temp_dir.path().join("tool-stored");
+        write(&stored_path, "tool").unwrap();
+        let mut be = BinaryEntry {
+            name: "tool".to_string(),
+            version: "v1".to_string(),
+            path: temp_dir.path().join("tool"),
+            source: "https://example.com/tool".to_string(),
+            stored_path: Some(stored_path.to_owned()),
+            sha256: Some(cache::sha256_file(&stored_path).unwrap()),
+            asset: None,
+        };
+
+        let verifications = verify_entry(&be, &[]);
+        assert!(verifications[0].is_ok());
+        assert!(matches!(verifications[1].status, FileStatus::Missing));
+
+        write(&be.path, "not a link").unwrap();
+        write(&stored_path, "tampered").unwrap();
+        let verifications = verify_entry(&be, &[]);
+        assert!(matches!(
+            verifications[0].status,
+            FileStatus::Modified { .. }
+        ));
+        assert!(matches!(verifications[1].status, FileStatus::Replaced));
+
+        be.sha256 = None;
+        assert!(matches!(
+            verify_entry(&be, &[])[0].status,
+            FileStatus::Unrecorded
+        ));
+    }
+}